@@ -0,0 +1,360 @@
+//! Software replacement for the ALERT pin the INA219 does not have.
+//!
+//! Limits are checked against every [`Measurement`] fed to a [`Monitor`]:
+//!
+//! ```ignore
+//! let mut monitor = Monitor::new();
+//! let over_current = monitor.add(Limit::above(Quantity::Current, 1500 * MilliAmpere).debounce(3));
+//! monitor.add(Limit::below(Quantity::BusVoltage, 4800 * MilliVolt).hysteresis(100 * MilliVolt));
+//! loop {
+//!     monitor.poll(&mut ina)?;
+//!     for event in monitor.events() { ... }
+//! }
+//! ```
+
+use alloc::boxed::Box;
+use alloc::vec::{Drain, Vec};
+
+use crate::ina219::Measurement;
+use crate::sensor::PowerSensor;
+
+/// The reading a [`Limit`] applies to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Quantity {
+    ShuntVoltage,
+    BusVoltage,
+    Current,
+    Power,
+}
+
+impl Quantity {
+    pub fn of(self, m: &Measurement) -> i64 {
+        match self {
+            Quantity::ShuntVoltage => m.shunt_voltage,
+            Quantity::BusVoltage => m.bus_voltage,
+            Quantity::Current => m.current,
+            Quantity::Power => m.power,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Comparison {
+    Above,
+    Below,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    /// The alert clears as soon as the reading is back within the limit.
+    Transparent,
+    /// The alert stays active until acknowledged.
+    Latched,
+}
+
+/// A threshold on one quantity, in the nano-units of [`crate::physic`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Limit {
+    pub quantity: Quantity,
+    pub comparison: Comparison,
+    pub threshold: i64,
+    /// How far the reading has to come back past the threshold to clear.
+    pub hysteresis: i64,
+    /// Consecutive readings needed to assert or clear the alert.
+    pub debounce: u32,
+    pub mode: Mode,
+}
+
+impl Limit {
+    /// Trips when the reading exceeds `threshold`.
+    pub fn above(quantity: Quantity, threshold: i64) -> Limit {
+        Limit::new(quantity, Comparison::Above, threshold)
+    }
+
+    /// Trips when the reading drops below `threshold`.
+    pub fn below(quantity: Quantity, threshold: i64) -> Limit {
+        Limit::new(quantity, Comparison::Below, threshold)
+    }
+
+    fn new(quantity: Quantity, comparison: Comparison, threshold: i64) -> Limit {
+        Limit {
+            quantity,
+            comparison,
+            threshold,
+            hysteresis: 0,
+            debounce: 1,
+            mode: Mode::Transparent,
+        }
+    }
+
+    pub fn hysteresis(mut self, hysteresis: i64) -> Limit {
+        self.hysteresis = hysteresis.abs();
+        self
+    }
+
+    pub fn debounce(mut self, samples: u32) -> Limit {
+        self.debounce = samples.max(1);
+        self
+    }
+
+    pub fn latched(mut self) -> Limit {
+        self.mode = Mode::Latched;
+        self
+    }
+
+    fn tripped(&self, value: i64) -> bool {
+        match self.comparison {
+            Comparison::Above => value > self.threshold,
+            Comparison::Below => value < self.threshold,
+        }
+    }
+
+    fn cleared(&self, value: i64) -> bool {
+        match self.comparison {
+            Comparison::Above => value <= self.threshold.saturating_sub(self.hysteresis),
+            Comparison::Below => value >= self.threshold.saturating_add(self.hysteresis),
+        }
+    }
+}
+
+/// Handle returned by [`Monitor::add`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct LimitId(usize);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EventKind {
+    Asserted,
+    Cleared,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Event {
+    pub limit: LimitId,
+    pub kind: EventKind,
+    /// The reading that caused the event.
+    pub value: i64,
+}
+
+type Callback<'a> = Box<dyn FnMut(&Event) + 'a>;
+
+struct State {
+    limit: Limit,
+    active: bool,
+    acknowledged: bool,
+    // the reading has been back within the limit for `debounce` samples
+    clear: bool,
+    // consecutive readings towards the next change of `active` or `clear`
+    count: u32,
+    last: i64,
+}
+
+/// Checks readings against a set of limits.
+///
+/// Events go to the callback set with [`Monitor::on_event`], or, without
+/// one, are queued until taken with [`Monitor::events`].
+#[derive(Default)]
+pub struct Monitor<'a> {
+    states: Vec<State>,
+    events: Vec<Event>,
+    callback: Option<Callback<'a>>,
+}
+
+impl<'a> Monitor<'a> {
+    pub fn new() -> Monitor<'a> {
+        Monitor {
+            states: Vec::new(),
+            events: Vec::new(),
+            callback: None,
+        }
+    }
+
+    pub fn add(&mut self, limit: Limit) -> LimitId {
+        self.states.push(State {
+            limit,
+            active: false,
+            acknowledged: false,
+            clear: false,
+            count: 0,
+            last: 0,
+        });
+        LimitId(self.states.len() - 1)
+    }
+
+    pub fn limit(&self, id: LimitId) -> &Limit {
+        &self.states[id.0].limit
+    }
+
+    pub fn on_event<F: FnMut(&Event) + 'a>(&mut self, callback: F) {
+        self.callback = Some(Box::new(callback));
+    }
+
+    /// Whether the alert of `id` is asserted.
+    pub fn is_active(&self, id: LimitId) -> bool {
+        self.states[id.0].active
+    }
+
+    /// Whether any alert is asserted, like the ALERT pin would be.
+    pub fn alert(&self) -> bool {
+        self.states.iter().any(|s| s.active)
+    }
+
+    /// Releases a latched alert. It clears now if the reading is already
+    /// back within the limit, otherwise as soon as it is.
+    pub fn acknowledge(&mut self, id: LimitId) {
+        let state = &mut self.states[id.0];
+        if !state.active {
+            return;
+        }
+        state.acknowledged = true;
+        if state.clear {
+            state.active = false;
+            let event = Event {
+                limit: id,
+                kind: EventKind::Cleared,
+                value: state.last,
+            };
+            self.emit(event);
+        }
+    }
+
+    /// Checks `m` against every limit.
+    pub fn check(&mut self, m: &Measurement) {
+        for i in 0..self.states.len() {
+            let state = &mut self.states[i];
+            let limit = state.limit;
+            let value = limit.quantity.of(m);
+            state.last = value;
+            let kind = if !state.active {
+                state.count = if limit.tripped(value) { state.count + 1 } else { 0 };
+                if state.count < limit.debounce {
+                    continue;
+                }
+                state.active = true;
+                state.acknowledged = false;
+                state.clear = false;
+                state.count = 0;
+                EventKind::Asserted
+            } else {
+                if !limit.cleared(value) {
+                    state.clear = false;
+                    state.count = 0;
+                } else if !state.clear {
+                    state.count += 1;
+                    if state.count >= limit.debounce {
+                        state.clear = true;
+                        state.count = 0;
+                    }
+                }
+                if !state.clear || (limit.mode == Mode::Latched && !state.acknowledged) {
+                    continue;
+                }
+                state.active = false;
+                EventKind::Cleared
+            };
+            self.emit(Event {
+                limit: LimitId(i),
+                kind,
+                value,
+            });
+        }
+    }
+
+    /// Takes a reading from `sensor` and checks it.
+    pub fn poll<S: PowerSensor>(&mut self, sensor: &mut S) -> Result<Measurement, S::Error> {
+        let m = sensor.measure()?;
+        self.check(&m);
+        Ok(m)
+    }
+
+    /// Queued events, oldest first.
+    pub fn events(&mut self) -> Drain<'_, Event> {
+        self.events.drain(..)
+    }
+
+    fn emit(&mut self, event: Event) {
+        match self.callback {
+            Some(ref mut callback) => callback(&event),
+            None => self.events.push(event),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ina219::{Calibration, INA219};
+    use crate::physic::{MilliAmpere, MilliVolt, Watt};
+    use crate::sim::SimulatedINA219;
+    use core::cell::RefCell;
+
+    fn current(milliamps: i64) -> Measurement {
+        Measurement {
+            current: milliamps * MilliAmpere,
+            ..Measurement::default()
+        }
+    }
+
+    fn run(monitor: &mut Monitor, readings: &[i64]) -> Vec<(usize, EventKind)> {
+        let mut events = Vec::new();
+        for (n, &milliamps) in readings.iter().enumerate() {
+            monitor.check(&current(milliamps));
+            events.extend(monitor.events().map(|e| (n, e.kind)));
+        }
+        events
+    }
+
+    #[test]
+    fn transparent_with_hysteresis() {
+        let mut monitor = Monitor::new();
+        let id = monitor.add(Limit::above(Quantity::Current, 1500 * MilliAmpere).hysteresis(100 * MilliAmpere));
+        let events = run(&mut monitor, &[1000, 1500, 1501, 1450, 1400, 1600]);
+        assert_eq!(events, [(2, EventKind::Asserted), (4, EventKind::Cleared), (5, EventKind::Asserted)]);
+        assert!(monitor.is_active(id));
+        assert!(monitor.alert());
+    }
+
+    #[test]
+    fn debounce_needs_consecutive_readings() {
+        let mut monitor = Monitor::new();
+        monitor.add(Limit::above(Quantity::Current, 1500 * MilliAmpere).debounce(3));
+        let events = run(&mut monitor, &[2000, 2000, 0, 2000, 2000, 2000, 0, 2000, 0, 0, 0]);
+        assert_eq!(events, [(5, EventKind::Asserted), (10, EventKind::Cleared)]);
+    }
+
+    #[test]
+    fn latched_until_acknowledged() {
+        let mut monitor = Monitor::new();
+        let id = monitor.add(Limit::above(Quantity::Current, 1500 * MilliAmpere).latched());
+        assert_eq!(run(&mut monitor, &[2000, 0, 0]), [(0, EventKind::Asserted)]);
+        assert!(monitor.is_active(id));
+        monitor.acknowledge(id);
+        assert!(!monitor.is_active(id));
+        assert_eq!(monitor.events().map(|e| (e.kind, e.value)).collect::<Vec<_>>(), [(EventKind::Cleared, 0)]);
+
+        // acknowledged while still over the limit: clears once it is not
+        assert_eq!(run(&mut monitor, &[2000]), [(0, EventKind::Asserted)]);
+        monitor.acknowledge(id);
+        assert!(monitor.is_active(id));
+        assert_eq!(run(&mut monitor, &[2000, 0]), [(1, EventKind::Cleared)]);
+    }
+
+    #[test]
+    fn callback_receives_events() {
+        let seen = RefCell::new(Vec::new());
+        let mut monitor = Monitor::new();
+        monitor.on_event(|e| seen.borrow_mut().push(*e));
+        let under_voltage = monitor.add(Limit::below(Quantity::BusVoltage, 4800 * MilliVolt));
+        let over_power = monitor.add(Limit::above(Quantity::Power, 5 * Watt));
+
+        let mut ina = INA219::new(SimulatedINA219::with_inputs(150_000, 4_000));
+        ina.init(Calibration::Calibration_32V_2A).unwrap();
+        monitor.poll(&mut ina).unwrap();
+        assert_eq!(monitor.events().count(), 0);
+        drop(monitor);
+
+        let seen = seen.into_inner();
+        assert_eq!(seen.iter().map(|e| (e.limit, e.kind)).collect::<Vec<_>>(), [(under_voltage, EventKind::Asserted), (over_power, EventKind::Asserted)]);
+        assert_eq!(seen[0].value, 4000 * MilliVolt);
+        assert_eq!(seen[1].value, 6 * Watt);
+    }
+}
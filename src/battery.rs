@@ -0,0 +1,402 @@
+//! State of charge of a battery pack measured with an INA219 in series.
+//!
+//! Coulomb counting integrates the current between readings. The count is
+//! seeded, and corrected whenever the pack has rested, from the
+//! open-circuit voltage of the chemistry. Full and empty events, detected
+//! from the voltage or reported by the charger, pin the count to the ends of
+//! the scale, and a full discharge from full to empty re-learns the capacity.
+//!
+//! ```ignore
+//! let mut battery = Battery::new(Chemistry::LiIon, 2, 2600 * MilliAmpereHour);
+//! loop {
+//!     battery.poll(&mut ina, interval)?;
+//!     let soc = battery.soc_percent();
+//!     let left = battery.time_to_empty();
+//! }
+//! ```
+//!
+//! Positive current charges the pack, see [`Direction`].
+
+use core::time::Duration;
+
+use crate::ina219::{Direction, Measurement};
+use crate::physic::{ElectricCharge, ElectricCurrent, ElectricPotential, MilliAmpere, MilliVolt};
+use crate::sensor::PowerSensor;
+
+/// Cell chemistry, selecting the open-circuit voltage curve and the
+/// voltages full and empty events are detected at.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Chemistry {
+    LiIon,
+    LiFePO4,
+}
+
+// Rested cell voltage against state of charge in per mille.
+const LI_ION_OCV: [(ElectricPotential, u16); 12] = [
+    (3000 * MilliVolt, 0),
+    (3450 * MilliVolt, 50),
+    (3610 * MilliVolt, 100),
+    (3680 * MilliVolt, 200),
+    (3730 * MilliVolt, 300),
+    (3770 * MilliVolt, 400),
+    (3810 * MilliVolt, 500),
+    (3870 * MilliVolt, 600),
+    (3930 * MilliVolt, 700),
+    (4000 * MilliVolt, 800),
+    (4080 * MilliVolt, 900),
+    (4200 * MilliVolt, 1000),
+];
+
+const LIFEPO4_OCV: [(ElectricPotential, u16); 11] = [
+    (2500 * MilliVolt, 0),
+    (3000 * MilliVolt, 100),
+    (3200 * MilliVolt, 200),
+    (3220 * MilliVolt, 300),
+    (3250 * MilliVolt, 400),
+    (3260 * MilliVolt, 500),
+    (3270 * MilliVolt, 600),
+    (3300 * MilliVolt, 700),
+    (3320 * MilliVolt, 800),
+    (3350 * MilliVolt, 900),
+    (3400 * MilliVolt, 1000),
+];
+
+impl Chemistry {
+    /// Rested cell voltage and state of charge in per mille, ascending.
+    pub fn ocv_table(self) -> &'static [(ElectricPotential, u16)] {
+        match self {
+            Chemistry::LiIon => &LI_ION_OCV,
+            Chemistry::LiFePO4 => &LIFEPO4_OCV,
+        }
+    }
+
+    /// Cell voltage a charger holds at the end of charge.
+    pub fn charged_voltage(self) -> ElectricPotential {
+        match self {
+            Chemistry::LiIon => 4150 * MilliVolt,
+            Chemistry::LiFePO4 => 3550 * MilliVolt,
+        }
+    }
+
+    /// Cell voltage under load at which the cell counts as empty.
+    pub fn empty_voltage(self) -> ElectricPotential {
+        match self {
+            Chemistry::LiIon => 3000 * MilliVolt,
+            Chemistry::LiFePO4 => 2800 * MilliVolt,
+        }
+    }
+
+    /// State of charge in per mille of a rested cell at `cell_voltage`,
+    /// interpolated linearly in the table.
+    pub fn soc_at(self, cell_voltage: ElectricPotential) -> u16 {
+        let table = self.ocv_table();
+        let (first, last) = (table[0], table[table.len() - 1]);
+        if cell_voltage <= first.0 {
+            return first.1;
+        }
+        if cell_voltage >= last.0 {
+            return last.1;
+        }
+        let i = table.iter().position(|&(v, _)| v > cell_voltage).unwrap_or(table.len() - 1);
+        let ((v0, s0), (v1, s1)) = (table[i - 1], table[i]);
+        (s0 as i64 + (s1 - s0) as i64 * (cell_voltage - v0) / (v1 - v0)) as u16
+    }
+}
+
+/// Ends of the scale, as returned by [`Battery::update`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Event {
+    Full,
+    Empty,
+}
+
+pub struct Battery {
+    chemistry: Chemistry,
+    cells: u32,
+    capacity: ElectricCharge,
+    // None until the first reading seeds it
+    charge: Option<ElectricCharge>,
+    deadband: ElectricCurrent,
+    rest: Duration,
+    resting: Duration,
+    reversed: bool,
+    current: ElectricCurrent,
+    // the previous reading was charging and did not end in a full event
+    charging: bool,
+    // net charge taken out since the last full event
+    since_full: Option<ElectricCharge>,
+}
+
+impl Battery {
+    /// A pack of `cells` in series with a nominal `capacity`. The state of
+    /// charge is seeded from the voltage of the first reading.
+    pub fn new(chemistry: Chemistry, cells: u32, capacity: ElectricCharge) -> Battery {
+        Battery {
+            chemistry,
+            cells: cells.max(1),
+            capacity,
+            charge: None,
+            deadband: 10 * MilliAmpere,
+            rest: Duration::from_secs(30 * 60),
+            resting: Duration::ZERO,
+            reversed: false,
+            current: 0,
+            charging: false,
+            since_full: None,
+        }
+    }
+
+    /// Currents within ±`deadband` count as idle.
+    pub fn deadband(mut self, deadband: ElectricCurrent) -> Battery {
+        self.deadband = deadband.abs();
+        self
+    }
+
+    /// How long the pack has to be idle before its voltage is trusted as
+    /// open-circuit voltage, 30 minutes by default.
+    pub fn rest(mut self, rest: Duration) -> Battery {
+        self.rest = rest;
+        self
+    }
+
+    /// For a shunt wired so that charging reads negative.
+    pub fn reversed(mut self) -> Battery {
+        self.reversed = true;
+        self
+    }
+
+    /// Counts the charge that flowed over `elapsed` at the current of `m`,
+    /// then checks for full and empty.
+    pub fn update(&mut self, m: &Measurement, elapsed: Duration) -> Option<Event> {
+        let current = if self.reversed { -m.current } else { m.current };
+        self.current = current;
+        let cell_voltage = m.bus_voltage / self.cells as i64;
+        let delta = (current as i128 * elapsed.as_nanos() as i128 / 1_000_000_000) as ElectricCharge;
+        let charge = match self.charge {
+            Some(charge) => charge + delta,
+            None => self.ocv_charge(cell_voltage),
+        };
+        self.charge = Some(charge.clamp(0, self.capacity));
+        if let Some(ref mut since_full) = self.since_full {
+            *since_full -= delta;
+        }
+
+        match Direction::of(current, self.deadband) {
+            Direction::Idle => {
+                self.resting += elapsed;
+                // the charger terminated, the pack still sits near its voltage
+                if self.charging && cell_voltage >= self.chemistry.charged_voltage() {
+                    self.charging = false;
+                    self.mark_full();
+                    return Some(Event::Full);
+                }
+                self.charging = false;
+                if self.resting >= self.rest {
+                    self.charge = Some(self.ocv_charge(cell_voltage));
+                }
+                None
+            }
+            Direction::Charging => {
+                self.resting = Duration::ZERO;
+                self.charging = true;
+                // tapered below C/20 at the charge voltage; nC / s = nA. Small
+                // packs taper below the deadband instead, which the Idle
+                // branch handles once the charger drops into it.
+                let taper = self.capacity / (20 * 3600);
+                if cell_voltage >= self.chemistry.charged_voltage() && current <= taper {
+                    self.charging = false;
+                    self.mark_full();
+                    return Some(Event::Full);
+                }
+                None
+            }
+            Direction::Discharging => {
+                self.resting = Duration::ZERO;
+                self.charging = false;
+                if cell_voltage <= self.chemistry.empty_voltage() {
+                    self.mark_empty();
+                    return Some(Event::Empty);
+                }
+                None
+            }
+        }
+    }
+
+    /// Takes a reading from `sensor` and counts it over `elapsed`.
+    pub fn poll<S: PowerSensor>(&mut self, sensor: &mut S, elapsed: Duration) -> Result<Option<Event>, S::Error> {
+        let m = sensor.measure()?;
+        Ok(self.update(&m, elapsed))
+    }
+
+    /// The pack is full, e.g. as signalled by the charger.
+    pub fn mark_full(&mut self) {
+        self.charge = Some(self.capacity);
+        self.since_full = Some(0);
+    }
+
+    /// The pack is empty, e.g. the low-voltage cutoff tripped. After a
+    /// discharge all the way from full, what came out is the new capacity.
+    pub fn mark_empty(&mut self) {
+        if let Some(taken) = self.since_full.take() {
+            // ignore learning from a charger that stopped early
+            if taken > self.capacity / 2 {
+                self.capacity = taken;
+            }
+        }
+        self.charge = Some(0);
+    }
+
+    pub fn capacity(&self) -> ElectricCharge {
+        self.capacity
+    }
+
+    /// Charge left in the pack, zero before the first reading.
+    pub fn remaining(&self) -> ElectricCharge {
+        self.charge.unwrap_or(0)
+    }
+
+    pub fn soc_percent(&self) -> f32 {
+        if self.capacity <= 0 {
+            return 0.0;
+        }
+        (self.remaining() as f64 * 100.0 / self.capacity as f64) as f32
+    }
+
+    pub fn direction(&self) -> Direction {
+        Direction::of(self.current, self.deadband)
+    }
+
+    /// At the last current, while discharging.
+    pub fn time_to_empty(&self) -> Option<Duration> {
+        match self.direction() {
+            Direction::Discharging => Some(duration_for(self.remaining(), -self.current)),
+            _ => None,
+        }
+    }
+
+    /// At the last current, while charging.
+    pub fn time_to_full(&self) -> Option<Duration> {
+        match self.direction() {
+            Direction::Charging => Some(duration_for(self.capacity - self.remaining(), self.current)),
+            _ => None,
+        }
+    }
+
+    fn ocv_charge(&self, cell_voltage: ElectricPotential) -> ElectricCharge {
+        (self.capacity as i128 * self.chemistry.soc_at(cell_voltage) as i128 / 1000) as ElectricCharge
+    }
+}
+
+// Time for `charge` to flow at `current`: nC / nA = s.
+fn duration_for(charge: ElectricCharge, current: ElectricCurrent) -> Duration {
+    Duration::from_nanos((charge as i128 * 1_000_000_000 / current as i128) as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ina219::{Calibration, INA219};
+    use crate::physic::{Ampere, AmpereHour, MilliAmpereHour, Volt};
+    use crate::sim::SimulatedINA219;
+
+    fn reading(current: ElectricCurrent, bus_voltage: ElectricPotential) -> Measurement {
+        Measurement {
+            current,
+            bus_voltage,
+            ..Measurement::default()
+        }
+    }
+
+    const MINUTE: Duration = Duration::from_secs(60);
+
+    #[test]
+    fn ocv_lookup() {
+        assert_eq!(Chemistry::LiIon.soc_at(3810 * MilliVolt), 500);
+        assert_eq!(Chemistry::LiIon.soc_at(3840 * MilliVolt), 550);
+        assert_eq!(Chemistry::LiIon.soc_at(5 * Volt), 1000);
+        assert_eq!(Chemistry::LiIon.soc_at(0), 0);
+        assert_eq!(Chemistry::LiFePO4.soc_at(3260 * MilliVolt), 500);
+    }
+
+    #[test]
+    fn counts_coulombs() {
+        let mut battery = Battery::new(Chemistry::LiIon, 2, 2 * AmpereHour);
+        // seeded at 50 % from the first reading
+        assert_eq!(battery.update(&reading(-Ampere, 7620 * MilliVolt), Duration::ZERO), None);
+        assert_eq!(battery.remaining(), AmpereHour);
+        battery.update(&reading(-Ampere, 7500 * MilliVolt), 30 * MINUTE);
+        assert_eq!(battery.soc_percent(), 25.0);
+        assert_eq!(battery.direction(), Direction::Discharging);
+        assert_eq!(battery.time_to_empty(), Some(30 * MINUTE));
+        assert_eq!(battery.time_to_full(), None);
+
+        battery.update(&reading(500 * MilliAmpere, 7800 * MilliVolt), 60 * MINUTE);
+        assert_eq!(battery.remaining(), AmpereHour);
+        assert_eq!(battery.time_to_full(), Some(120 * MINUTE));
+    }
+
+    #[test]
+    fn rest_trusts_open_circuit_voltage() {
+        let mut battery = Battery::new(Chemistry::LiFePO4, 4, 100 * AmpereHour).rest(10 * MINUTE);
+        battery.update(&reading(0, 13_040 * MilliVolt), Duration::ZERO);
+        assert_eq!(battery.soc_percent(), 50.0);
+        // counting drifted, the rested voltage says 80 %
+        battery.update(&reading(0, 13_280 * MilliVolt), 5 * MINUTE);
+        assert_eq!(battery.soc_percent(), 50.0);
+        battery.update(&reading(0, 13_280 * MilliVolt), 5 * MINUTE);
+        assert_eq!(battery.soc_percent(), 80.0);
+    }
+
+    #[test]
+    fn learns_capacity_from_full_to_empty() {
+        let mut battery = Battery::new(Chemistry::LiIon, 1, 2 * AmpereHour);
+        battery.update(&reading(0, 3900 * MilliVolt), Duration::ZERO);
+        // charger tapers off at the charge voltage
+        assert_eq!(battery.update(&reading(80 * MilliAmpere, 4180 * MilliVolt), MINUTE), Some(Event::Full));
+        assert_eq!(battery.soc_percent(), 100.0);
+        battery.update(&reading(-1800 * MilliAmpere, 3700 * MilliVolt), 60 * MINUTE);
+        assert_eq!(battery.update(&reading(-1800 * MilliAmpere, 2950 * MilliVolt), Duration::ZERO), Some(Event::Empty));
+        assert_eq!(battery.capacity(), 1800 * MilliAmpereHour);
+        assert_eq!(battery.remaining(), 0);
+
+        // an empty event without a full one before does not learn
+        battery.mark_empty();
+        assert_eq!(battery.capacity(), 1800 * MilliAmpereHour);
+    }
+
+    #[test]
+    fn full_when_the_charger_terminates() {
+        let mut battery = Battery::new(Chemistry::LiIon, 1, 2 * AmpereHour);
+        battery.update(&reading(0, 3900 * MilliVolt), Duration::ZERO);
+        battery.update(&reading(500 * MilliAmpere, 4180 * MilliVolt), MINUTE);
+        assert_eq!(battery.update(&reading(0, 4170 * MilliVolt), MINUTE), Some(Event::Full));
+        assert_eq!(battery.soc_percent(), 100.0);
+        // only once, and not for a pack that just rests high
+        assert_eq!(battery.update(&reading(0, 4170 * MilliVolt), MINUTE), None);
+    }
+
+    #[test]
+    fn small_packs_taper_within_the_deadband() {
+        // C/20 of 100 mAh is 5 mA, below the 10 mA deadband
+        let mut battery = Battery::new(Chemistry::LiIon, 1, 100 * MilliAmpereHour);
+        battery.update(&reading(0, 3900 * MilliVolt), Duration::ZERO);
+        battery.update(&reading(50 * MilliAmpere, 4100 * MilliVolt), MINUTE);
+        assert_eq!(battery.update(&reading(12 * MilliAmpere, 4180 * MilliVolt), MINUTE), None);
+        assert_eq!(battery.update(&reading(10 * MilliAmpere, 4180 * MilliVolt), MINUTE), Some(Event::Full));
+    }
+
+    #[test]
+    fn polls_the_driver() {
+        // discharging: current flows from IN- to IN+
+        let mut ina = INA219::new(SimulatedINA219::with_inputs(-50_000, 4_000));
+        ina.init(Calibration::Calibration_32V_1A).unwrap();
+        let mut battery = Battery::new(Chemistry::LiIon, 1, 2 * AmpereHour);
+        battery.poll(&mut ina, Duration::ZERO).unwrap();
+        battery.poll(&mut ina, 60 * MINUTE).unwrap();
+        assert_eq!(battery.remaining(), 1100 * MilliAmpereHour);
+
+        let mut reversed = Battery::new(Chemistry::LiIon, 1, 2 * AmpereHour).reversed();
+        reversed.poll(&mut ina, Duration::ZERO).unwrap();
+        assert_eq!(reversed.direction(), Direction::Charging);
+    }
+}
@@ -0,0 +1,558 @@
+//! Command line tool to read an INA219 through /dev/i2c-N.
+//!
+//! Build with `cargo build --features cli --bin ina219`.
+
+extern crate ina219_rs;
+extern crate linux_embedded_hal;
+
+use std::env;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::process;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use ina219_rs::ina219::{BusVoltageRange, Calibration, Gain, Measurement, Register, INA219};
+use ina219_rs::logger::{self, Header, Record, RotatingLogger, Rotation, Sink};
+use ina219_rs::metrics::{Exporter, Labels};
+use ina219_rs::physic::{
+    nanoAsDecimal, ElectricCurrent, ElectricResistance, PhysicElectricCurrentSet, PhysicElectricResistanceSet,
+    ToStringPhysic_current, ToStringPhysic_potential, ToStringPhysic_power, ToStringPhysic_resistance,
+};
+use linux_embedded_hal::I2cdev;
+
+const USAGE: &str = "usage: ina219 [options] <command>
+
+commands:
+  read                      read one sample
+  stream                    read samples continuously
+  dump                      print all registers
+  write <register> <value>  write a register (name or number, value in hex or decimal)
+  calibrate                 program the calibration and print the resulting LSBs
+  serve                     serve readings as OpenMetrics on http://<listen>/metrics
+  log <file>                capture samples to csv or json-lines files
+
+options:
+  --bus <path>              I2C bus device (default /dev/i2c-1)
+  --address <addr>          chip address (default 0x40)
+  --shunt <resistance>      shunt resistor, e.g. 100mΩ or 0.1Ohm
+  --max-current <current>   largest expected current, e.g. 2A
+  --preset <name>           32V_2A, 32V_1A or 16V_400mA (default 32V_2A)
+  --attach                  keep the chip's configuration, reading it with --shunt
+  --range <volts>           bus voltage range, 16V or 32V
+  --gain <millivolts>       shunt voltage range, 40mV, 80mV, 160mV or 320mV
+  --format <format>         table, csv or json (default table)
+  --rate <hz>               samples per second for stream (default 1)
+  --count <n>               stop stream or log after n samples
+  --rotate <n>              start a new log file every n samples
+  --listen <addr:port>      address for serve (default 127.0.0.1:9100)
+  --rail <name>             rail label for serve";
+
+// How long serve waits for a client to send its request or take the reply.
+const CLIENT_TIMEOUT: Duration = Duration::from_secs(5);
+
+// --rate limits, one sample a day to 10 kHz; beyond those the sample
+// period does not fit a Duration or the bus.
+const MIN_RATE: f64 = 1.0 / 86_400.0;
+const MAX_RATE: f64 = 10_000.0;
+
+const REGISTERS: [(&str, u8); 6] = [
+    ("config", Register::Configuration),
+    ("shunt", Register::ShuntVoltage),
+    ("bus", Register::BusVoltage),
+    ("power", Register::Power),
+    ("current", Register::Current),
+    ("calibration", Register::Calibration),
+];
+
+#[derive(Clone, Copy, PartialEq)]
+enum Format {
+    Table,
+    Csv,
+    Json,
+}
+
+enum Command {
+    Read,
+    Stream,
+    Dump,
+    Write(u8, u16),
+    Calibrate,
+    Serve,
+    Log(String),
+    Help,
+}
+
+struct Options {
+    bus: String,
+    address: u8,
+    shunt: Option<ElectricResistance>,
+    max_current: Option<ElectricCurrent>,
+    preset: Option<Calibration>,
+    attach: bool,
+    range: Option<BusVoltageRange>,
+    gain: Option<Gain>,
+    format: Format,
+    rate: f64,
+    count: Option<u64>,
+    rotate: Option<u64>,
+    listen: String,
+    rail: String,
+    command: Command,
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let options = match parse_args(&args) {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("ina219: {}\n\n{}", e, USAGE);
+            process::exit(2);
+        }
+    };
+    if let Command::Help = options.command {
+        println!("{}", USAGE);
+        return;
+    }
+    if let Err(e) = run(&options) {
+        eprintln!("ina219: {}", e);
+        process::exit(1);
+    }
+}
+
+fn parse_args(args: &[String]) -> Result<Options, String> {
+    let mut options = Options {
+        bus: String::from("/dev/i2c-1"),
+        address: 0x40,
+        shunt: None,
+        max_current: None,
+        preset: None,
+        attach: false,
+        range: None,
+        gain: None,
+        format: Format::Table,
+        rate: 1.0,
+        count: None,
+        rotate: None,
+        listen: String::from("127.0.0.1:9100"),
+        rail: String::new(),
+        command: Command::Read,
+    };
+    let mut command = None;
+    let mut it = args.iter();
+    while let Some(arg) = it.next() {
+        let mut value = || it.next().ok_or_else(|| format!("{} needs a value", arg));
+        match arg.as_str() {
+            "--bus" => options.bus = value()?.clone(),
+            "--address" => {
+                options.address = match parse_number(value()?)? {
+                    address @ 0..=0x7F => address as u8,
+                    address => return Err(format!("{:#x} is not a 7 bit address", address)),
+                }
+            }
+            "--shunt" => {
+                options.shunt = Some(ElectricResistance::setResistance(value()?).map_err(|e| e.to_string())?)
+            }
+            "--max-current" => {
+                options.max_current = Some(ElectricCurrent::setCurrent(value()?).map_err(|e| e.to_string())?)
+            }
+            "--preset" => {
+                options.preset = match value()?.as_str() {
+                    "32V_2A" => Some(Calibration::Calibration_32V_2A),
+                    "32V_1A" => Some(Calibration::Calibration_32V_1A),
+                    "16V_400mA" => Some(Calibration::Calibration_16V_400mA),
+                    other => return Err(format!("unknown preset {}", other)),
+                }
+            }
+            "--attach" => options.attach = true,
+            "--range" => {
+                options.range = match value()?.as_str() {
+                    "16V" => Some(BusVoltageRange::Range_16V),
+                    "32V" => Some(BusVoltageRange::Range_32V),
+                    other => return Err(format!("unknown range {}", other)),
+                }
+            }
+            "--gain" => {
+                options.gain = match value()?.as_str() {
+                    "40mV" => Some(Gain::Gain_1_40mV),
+                    "80mV" => Some(Gain::Gain_2_80mV),
+                    "160mV" => Some(Gain::Gain_4_160mV),
+                    "320mV" => Some(Gain::Gain_8_320mV),
+                    other => return Err(format!("unknown gain {}", other)),
+                }
+            }
+            "--format" => {
+                options.format = match value()?.as_str() {
+                    "table" => Format::Table,
+                    "csv" => Format::Csv,
+                    "json" => Format::Json,
+                    other => return Err(format!("unknown format {}", other)),
+                }
+            }
+            "--rate" => {
+                options.rate = value()?.parse().map_err(|_| String::from("--rate needs a number"))?;
+                if !(MIN_RATE..=MAX_RATE).contains(&options.rate) {
+                    return Err(String::from("--rate must be between 1/86400 and 10000"));
+                }
+            }
+            "--count" => options.count = Some(value()?.parse().map_err(|_| String::from("--count needs a number"))?),
+            "--rotate" => {
+                options.rotate = Some(value()?.parse().map_err(|_| String::from("--rotate needs a number"))?)
+            }
+            "--listen" => options.listen = value()?.clone(),
+            "--rail" => options.rail = value()?.clone(),
+            "-h" | "--help" => {
+                options.command = Command::Help;
+                return Ok(options);
+            }
+            "read" => command = Some(Command::Read),
+            "stream" => command = Some(Command::Stream),
+            "dump" => command = Some(Command::Dump),
+            "calibrate" => command = Some(Command::Calibrate),
+            "serve" => command = Some(Command::Serve),
+            "log" => command = Some(Command::Log(it.next().ok_or("log needs a file")?.clone())),
+            "write" => {
+                let register = parse_register(it.next().ok_or("write needs a register")?)?;
+                let value = parse_number(it.next().ok_or("write needs a value")?)?;
+                command = Some(Command::Write(register, value))
+            }
+            other => return Err(format!("unknown argument {}", other)),
+        }
+    }
+    if options.attach {
+        if options.shunt.is_none() || options.max_current.is_some() || options.preset.is_some() {
+            return Err(String::from("--attach needs --shunt and no --max-current or --preset"));
+        }
+    } else if options.shunt.is_some() != options.max_current.is_some() {
+        return Err(String::from("--shunt and --max-current go together"));
+    }
+    options.command = command.ok_or("no command given")?;
+    Ok(options)
+}
+
+fn parse_number(s: &str) -> Result<u16, String> {
+    let parsed = if s.starts_with("0x") || s.starts_with("0X") {
+        u16::from_str_radix(&s[2..], 16)
+    } else {
+        s.parse()
+    };
+    parsed.map_err(|_| format!("{} is not a 16 bit number", s))
+}
+
+fn parse_register(s: &str) -> Result<u8, String> {
+    for &(name, register) in REGISTERS.iter() {
+        if name == s {
+            return Ok(register);
+        }
+    }
+    match parse_number(s)? {
+        n if n <= Register::Calibration as u16 => Ok(n as u8),
+        _ => Err(format!("unknown register {}", s)),
+    }
+}
+
+fn run(options: &Options) -> Result<(), String> {
+    let i2c = I2cdev::new(&options.bus).map_err(|e| format!("{}: {}", options.bus, e))?;
+    let mut ina = match options.shunt {
+        Some(shunt) if options.attach => INA219::attach(i2c, options.address, shunt).map_err(|e| e.to_string())?,
+        _ => INA219::with_address(i2c, options.address),
+    };
+    match options.command {
+        // printed by main without opening the bus
+        Command::Help => Ok(()),
+        Command::Dump => dump(&mut ina, options.format),
+        Command::Write(register, value) => ina.write_register(register, value).map_err(|e| e.to_string()),
+        Command::Calibrate => {
+            configure(&mut ina, options)?;
+            print_calibration(&ina, options.format);
+            Ok(())
+        }
+        Command::Read => {
+            configure(&mut ina, options)?;
+            let m = ina.measure().map_err(|e| e.to_string())?;
+            print_header(options.format);
+            print_measurement(&m, timestamp(), options.format);
+            Ok(())
+        }
+        Command::Stream => {
+            configure(&mut ina, options)?;
+            stream(&mut ina, options)
+        }
+        Command::Serve => {
+            configure(&mut ina, options)?;
+            serve(&mut ina, options)
+        }
+        Command::Log(ref path) => {
+            configure(&mut ina, options)?;
+            log(&mut ina, path, options)
+        }
+    }
+}
+
+fn configure(ina: &mut INA219<I2cdev>, options: &Options) -> Result<(), String> {
+    match (options.shunt, options.max_current) {
+        _ if options.attach => {}
+        (Some(shunt), Some(max_current)) => ina.calibrate(shunt, max_current).map_err(|e| e.to_string())?,
+        _ => {
+            let preset = options.preset.clone().unwrap_or(Calibration::Calibration_32V_2A);
+            ina.init(preset).map_err(|e| e.to_string())?
+        }
+    }
+    if let Some(range) = options.range {
+        ina.set_bus_voltage_range(range).map_err(|e| e.to_string())?;
+    }
+    if let Some(gain) = options.gain {
+        ina.set_gain(gain).map_err(|e| e.to_string())?;
+    }
+    Ok(())
+}
+
+// Prints the records of logger::run to stdout.
+struct Printer(Format);
+
+impl Sink for Printer {
+    fn write_record(&mut self, record: &Record) -> std::io::Result<()> {
+        print_measurement(&record.measurement, record.time.as_secs_f64(), self.0);
+        Ok(())
+    }
+}
+
+fn stream(ina: &mut INA219<I2cdev>, options: &Options) -> Result<(), String> {
+    let period = Duration::from_secs_f64(1.0 / options.rate);
+    print_header(options.format);
+    logger::run(ina, &mut Printer(options.format), period, options.count).map_err(|e| e.to_string())
+}
+
+fn log(ina: &mut INA219<I2cdev>, path: &str, options: &Options) -> Result<(), String> {
+    let format = match options.format {
+        Format::Json => logger::Format::JsonLines,
+        _ => logger::Format::Csv,
+    };
+    let rotation = Rotation {
+        max_bytes: None,
+        max_records: options.rotate,
+    };
+    let mut sink =
+        RotatingLogger::create(path, format, &Header::of(ina), rotation).map_err(|e| format!("{}: {}", path, e))?;
+    let period = Duration::from_secs_f64(1.0 / options.rate);
+    let result = logger::run(ina, &mut sink, period, options.count);
+    sink.flush().map_err(|e| e.to_string())?;
+    result.map_err(|e| e.to_string())
+}
+
+fn serve(ina: &mut INA219<I2cdev>, options: &Options) -> Result<(), String> {
+    let listener = TcpListener::bind(&options.listen).map_err(|e| format!("{}: {}", options.listen, e))?;
+    let labels = Labels::new(&options.bus, options.address, &options.rail);
+    let mut exporter = Exporter::new();
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                eprintln!("ina219: {}", e);
+                continue;
+            }
+        };
+        if let Err(e) = respond(stream, ina, &labels, &mut exporter) {
+            eprintln!("ina219: {}", e);
+        }
+    }
+    Ok(())
+}
+
+// Serves one request. The sensor is only read for GET /metrics.
+fn respond(stream: TcpStream, ina: &mut INA219<I2cdev>, labels: &Labels, exporter: &mut Exporter) -> std::io::Result<()> {
+    // one client at a time, so a silent one must not block the others
+    stream.set_read_timeout(Some(CLIENT_TIMEOUT))?;
+    stream.set_write_timeout(Some(CLIENT_TIMEOUT))?;
+    let mut reader = BufReader::new(stream);
+    let mut request = String::new();
+    reader.read_line(&mut request)?;
+    // skip the headers, the request has no body
+    let mut line = String::new();
+    while reader.read_line(&mut line)? > 2 {
+        line.clear();
+    }
+    let mut stream = reader.into_inner();
+    let mut parts = request.split_whitespace();
+    match (parts.next(), parts.next()) {
+        (Some("GET"), Some("/metrics")) => {
+            exporter.clear();
+            exporter.scrape(labels.clone(), ina);
+            let body = exporter.render();
+            write!(
+                stream,
+                "HTTP/1.1 200 OK\r\nContent-Type: application/openmetrics-text; version=1.0.0; charset=utf-8\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                body.len(),
+                body
+            )
+        }
+        _ => write!(stream, "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"),
+    }
+}
+
+fn dump(ina: &mut INA219<I2cdev>, format: Format) -> Result<(), String> {
+    if format == Format::Csv {
+        println!("register,address,value");
+    }
+    for &(name, register) in REGISTERS.iter() {
+        let value = ina.read_register(register).map_err(|e| e.to_string())?;
+        match format {
+            Format::Table => println!("{:<12} 0x{:02x}  0x{:04x}  {:>6}", name, register, value, value),
+            Format::Csv => println!("{},{},{}", name, register, value),
+            Format::Json => println!("{{\"register\":\"{}\",\"address\":{},\"value\":{}}}", name, register, value),
+        }
+    }
+    Ok(())
+}
+
+fn print_calibration(ina: &INA219<I2cdev>, format: Format) {
+    let shunt = ina.shunt().to_string_physic_resistance();
+    let max_current = ina.max_current().to_string_physic_current();
+    let current_lsb = ina.current_lsb().to_string_physic_current();
+    let power_lsb = ina.power_lsb().to_string_physic_power();
+    match format {
+        Format::Table => {
+            println!("shunt        {}", shunt);
+            println!("max current  {}", max_current);
+            println!("current LSB  {}", current_lsb);
+            println!("power LSB    {}", power_lsb);
+            println!("calibration  0x{:04x}", ina.calibration_value());
+            println!("config       0x{:04x}", ina.config());
+        }
+        Format::Csv => {
+            println!("shunt_ohm,max_current_A,current_lsb_A,power_lsb_W,calibration,config");
+            println!(
+                "{},{},{},{},{},{}",
+                nanoAsDecimal(ina.shunt()),
+                nanoAsDecimal(ina.max_current()),
+                nanoAsDecimal(ina.current_lsb()),
+                nanoAsDecimal(ina.power_lsb()),
+                ina.calibration_value(),
+                ina.config()
+            );
+        }
+        Format::Json => println!(
+            "{{\"shunt_ohm\":{},\"max_current_A\":{},\"current_lsb_A\":{},\"power_lsb_W\":{},\"calibration\":{},\"config\":{}}}",
+            nanoAsDecimal(ina.shunt()),
+            nanoAsDecimal(ina.max_current()),
+            nanoAsDecimal(ina.current_lsb()),
+            nanoAsDecimal(ina.power_lsb()),
+            ina.calibration_value(),
+            ina.config()
+        ),
+    }
+}
+
+fn print_header(format: Format) {
+    match format {
+        Format::Table => println!(
+            "{:<15} {:>10} {:>10} {:>10} {:>10}",
+            "time", "bus", "shunt", "current", "power"
+        ),
+        Format::Csv => println!("time,bus_voltage_V,shunt_voltage_V,current_A,power_W"),
+        Format::Json => {}
+    }
+}
+
+fn print_measurement(m: &Measurement, time: f64, format: Format) {
+    match format {
+        Format::Table => println!(
+            "{:<15.3} {:>10} {:>10} {:>10} {:>10}",
+            time,
+            m.bus_voltage.to_string_physic_potential(),
+            m.shunt_voltage.to_string_physic_potential(),
+            m.current.to_string_physic_current(),
+            m.power.to_string_physic_power()
+        ),
+        Format::Csv => println!(
+            "{:.3},{},{},{},{}",
+            time,
+            nanoAsDecimal(m.bus_voltage),
+            nanoAsDecimal(m.shunt_voltage),
+            nanoAsDecimal(m.current),
+            nanoAsDecimal(m.power)
+        ),
+        Format::Json => println!(
+            "{{\"time\":{:.3},\"bus_voltage_V\":{},\"shunt_voltage_V\":{},\"current_A\":{},\"power_W\":{}}}",
+            time,
+            nanoAsDecimal(m.bus_voltage),
+            nanoAsDecimal(m.shunt_voltage),
+            nanoAsDecimal(m.current),
+            nanoAsDecimal(m.power)
+        ),
+    }
+}
+
+fn timestamp() -> f64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs_f64())
+        .unwrap_or(0.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Options, String> {
+        let args: Vec<String> = args.iter().map(|&a| String::from(a)).collect();
+        parse_args(&args)
+    }
+
+    #[test]
+    fn defaults() {
+        let options = parse(&["read"]).unwrap();
+        assert_eq!(options.bus, "/dev/i2c-1");
+        assert_eq!(options.address, 0x40);
+        assert_eq!(options.rate, 1.0);
+        assert_eq!(options.listen, "127.0.0.1:9100");
+        assert!(options.format == Format::Table);
+        assert!(options.preset.is_none());
+        assert!(matches!(options.command, Command::Read));
+    }
+
+    #[test]
+    fn parses_options_and_commands() {
+        let options = parse(&[
+            "--address", "0x45", "--preset", "16V_400mA", "--format", "csv", "--rate", "10", "--count", "5", "stream",
+        ])
+        .unwrap();
+        assert_eq!(options.address, 0x45);
+        assert_eq!(options.preset, Some(Calibration::Calibration_16V_400mA));
+        assert!(options.format == Format::Csv);
+        assert_eq!((options.rate, options.count), (10.0, Some(5)));
+        assert!(matches!(options.command, Command::Stream));
+
+        let options = parse(&["write", "calibration", "0x1000"]).unwrap();
+        assert!(matches!(options.command, Command::Write(5, 0x1000)));
+        let options = parse(&["--attach", "--shunt", "100mΩ", "--gain", "320mV", "read"]).unwrap();
+        assert_eq!(options.gain, Some(Gain::Gain_8_320mV));
+        assert!(matches!(parse(&["read", "--help"]).unwrap().command, Command::Help));
+    }
+
+    #[test]
+    fn rejects_bad_arguments() {
+        let error = |args: &[&str]| parse(args).err().unwrap();
+        assert_eq!(error(&[]), "no command given");
+        assert_eq!(error(&["frobnicate"]), "unknown argument frobnicate");
+        assert_eq!(error(&["--bus"]), "--bus needs a value");
+        assert_eq!(error(&["--address", "0x80", "read"]), "0x80 is not a 7 bit address");
+        assert_eq!(error(&["--preset", "5V_1A", "read"]), "unknown preset 5V_1A");
+        assert_eq!(error(&["--format", "xml", "read"]), "unknown format xml");
+        assert_eq!(error(&["--gain", "20mV", "read"]), "unknown gain 20mV");
+        assert_eq!(error(&["--rate", "fast", "read"]), "--rate needs a number");
+        for rate in ["0", "-1", "1e-20", "1e9", "NaN", "inf"] {
+            assert_eq!(error(&["--rate", rate, "read"]), "--rate must be between 1/86400 and 10000", "{}", rate);
+        }
+        assert_eq!(error(&["--shunt", "100mΩ", "read"]), "--shunt and --max-current go together");
+        assert_eq!(error(&["write", "status", "1"]), "status is not a 16 bit number");
+        assert_eq!(error(&["write", "6", "1"]), "unknown register 6");
+    }
+
+    #[test]
+    fn attach_takes_only_a_shunt() {
+        let message = "--attach needs --shunt and no --max-current or --preset";
+        assert_eq!(parse(&["--attach", "read"]).err().unwrap(), message);
+        assert_eq!(parse(&["--attach", "--shunt", "0.1Ohm", "--max-current", "2A", "read"]).err().unwrap(), message);
+        assert_eq!(parse(&["--attach", "--shunt", "0.1Ohm", "--preset", "32V_1A", "read"]).err().unwrap(), message);
+    }
+}
@@ -0,0 +1,296 @@
+//! Picks a shunt, PGA gain, bus voltage range and calibration for a new
+//! board, the way the comment block in `setCalibration_32V_2A` does by hand.
+//!
+//! Everything is `const fn`, so a board crate can fix its design at compile
+//! time:
+//!
+//! ```ignore
+//! const DESIGN: Design = match evaluate(47 * MilliOhm, &REQUIREMENTS) {
+//!     Ok(design) => design,
+//!     Err(_) => panic!("shunt does not fit"),
+//! };
+//! ina.calibrate(DESIGN.shunt, REQUIREMENTS.max_current)?;
+//! ina.set_bus_voltage_range(DESIGN.bus_voltage_range)?;
+//! ```
+
+use crate::ina219::{BusVoltageRange, Gain};
+use crate::physic::{Ampere, ElectricCurrent, ElectricPotential, ElectricResistance, MicroOhm, MicroVolt, Ohm, Power};
+
+/// Standard resistor value series, as mantissas times 100.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ESeries {
+    E12,
+    E24,
+    E96,
+}
+
+const E12: [u16; 12] = [100, 120, 150, 180, 220, 270, 330, 390, 470, 560, 680, 820];
+
+const E24: [u16; 24] = [
+    100, 110, 120, 130, 150, 160, 180, 200, 220, 240, 270, 300, 330, 360, 390, 430, 470, 510, 560, 620, 680, 750, 820,
+    910,
+];
+
+const E96: [u16; 96] = [
+    100, 102, 105, 107, 110, 113, 115, 118, 121, 124, 127, 130, 133, 137, 140, 143, 147, 150, 154, 158, 162, 165, 169,
+    174, 178, 182, 187, 191, 196, 200, 205, 210, 215, 221, 226, 232, 237, 243, 249, 255, 261, 267, 274, 280, 287, 294,
+    301, 309, 316, 324, 332, 340, 348, 357, 365, 374, 383, 392, 402, 412, 422, 432, 442, 453, 464, 475, 487, 499, 511,
+    523, 536, 549, 562, 576, 590, 604, 619, 634, 649, 665, 681, 698, 715, 732, 750, 768, 787, 806, 825, 845, 866, 887,
+    909, 931, 953, 976,
+];
+
+impl ESeries {
+    pub const fn values(self) -> &'static [u16] {
+        match self {
+            ESeries::E12 => &E12,
+            ESeries::E24 => &E24,
+            ESeries::E96 => &E96,
+        }
+    }
+}
+
+// Decades searched by `recommend`, 100 µΩ to 9.76 Ω.
+const SMALLEST_DECADE: ElectricResistance = 100 * MicroOhm;
+const LARGEST_DECADE: ElectricResistance = Ohm;
+
+// Shunt voltage register step.
+const SHUNT_LSB: ElectricPotential = 10 * MicroVolt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Requirements {
+    pub max_current: ElectricCurrent,
+    /// Coarsest acceptable current step.
+    pub resolution: ElectricCurrent,
+    pub max_bus_voltage: ElectricPotential,
+    /// Largest acceptable drop across the shunt at `max_current`.
+    pub max_burden: ElectricPotential,
+    /// Largest acceptable shunt dissipation at `max_current`.
+    pub max_dissipation: Power,
+}
+
+/// Why a shunt does not meet the requirements.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Unsuitable {
+    /// The bus voltage exceeds the 32 V range, or a requirement is not
+    /// positive.
+    Requirements,
+    /// More than 320 mV across the shunt at `max_current`.
+    ShuntVoltage,
+    Burden,
+    Dissipation,
+    Resolution,
+    /// No calibration register value fits the shunt.
+    Calibration,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Design {
+    pub shunt: ElectricResistance,
+    pub gain: Gain,
+    pub bus_voltage_range: BusVoltageRange,
+    pub current_lsb: ElectricCurrent,
+    pub power_lsb: Power,
+    pub calibration: u16,
+    /// Smallest current step: the current LSB or one 10 µV shunt step,
+    /// whichever is coarser.
+    pub resolution: ElectricCurrent,
+    /// Current at which the shunt ADC or the current register saturates.
+    pub overflow_current: ElectricCurrent,
+    /// Shunt voltage at `max_current`.
+    pub burden: ElectricPotential,
+    /// Shunt dissipation at `max_current`, the worst case the design is
+    /// specified for.
+    pub dissipation: Power,
+}
+
+/// Checks one shunt against `requirements`.
+pub const fn evaluate(shunt: ElectricResistance, requirements: &Requirements) -> Result<Design, Unsuitable> {
+    let max_current = requirements.max_current;
+    if shunt <= 0 || max_current <= 0 || requirements.resolution <= 0 || requirements.max_bus_voltage < 0 {
+        return Err(Unsuitable::Requirements);
+    }
+    let bus_voltage_range = match BusVoltageRange::for_bus_voltage(requirements.max_bus_voltage) {
+        Some(range) => range,
+        None => return Err(Unsuitable::Requirements),
+    };
+    let burden = shunt_voltage_at(shunt, max_current);
+    let gain = match Gain::for_shunt_voltage(burden) {
+        Some(gain) => gain,
+        None => return Err(Unsuitable::ShuntVoltage),
+    };
+    if burden > requirements.max_burden {
+        return Err(Unsuitable::Burden);
+    }
+    let dissipation = (max_current as i128 * max_current as i128 / Ampere as i128 * shunt as i128 / Ohm as i128) as Power;
+    if dissipation > requirements.max_dissipation {
+        return Err(Unsuitable::Dissipation);
+    }
+    let (current_lsb, calibration) = match calibration_for(shunt, max_current) {
+        Some(c) => c,
+        None => return Err(Unsuitable::Calibration),
+    };
+    let step = current_at(shunt, SHUNT_LSB);
+    let resolution = if step > current_lsb { step } else { current_lsb };
+    if resolution > requirements.resolution {
+        return Err(Unsuitable::Resolution);
+    }
+    let adc_limit = current_at(shunt, gain.full_scale());
+    let register_limit = current_lsb * 32767;
+    Ok(Design {
+        shunt,
+        gain,
+        bus_voltage_range,
+        current_lsb,
+        power_lsb: 20 * current_lsb,
+        calibration,
+        resolution,
+        overflow_current: if adc_limit < register_limit { adc_limit } else { register_limit },
+        burden,
+        dissipation,
+    })
+}
+
+/// The largest shunt of `series` that meets `requirements`, which gives the
+/// finest resolution. Without one, the reason the closest candidate failed.
+pub const fn recommend(requirements: &Requirements, series: ESeries) -> Result<Design, Unsuitable> {
+    let values = series.values();
+    let mut decade = LARGEST_DECADE;
+    let mut error = Unsuitable::Requirements;
+    while decade >= SMALLEST_DECADE {
+        let mut i = values.len();
+        while i > 0 {
+            i -= 1;
+            let shunt = values[i] as ElectricResistance * decade / 100;
+            match evaluate(shunt, requirements) {
+                Ok(design) => return Ok(design),
+                // smaller shunts only resolve less
+                Err(Unsuitable::Resolution) => return Err(Unsuitable::Resolution),
+                Err(Unsuitable::Requirements) => return Err(Unsuitable::Requirements),
+                Err(e) => error = e,
+            }
+        }
+        decade /= 10;
+    }
+    Err(error)
+}
+
+/// Cal = trunc(0.04096 / (Current_LSB * RSHUNT)), with both in nano units.
+pub const fn calibration_register(current_lsb: ElectricCurrent, shunt: ElectricResistance) -> i128 {
+    40_960_000_000_000_000i128 / (current_lsb as i128 * shunt as i128)
+}
+
+/// The first 1-2-5 step at or above `max_current / 32767`.
+pub const fn current_lsb_for(max_current: ElectricCurrent) -> ElectricCurrent {
+    let min_lsb = (max_current + 32766) / 32767;
+    let mut lsb = 1;
+    while lsb < min_lsb {
+        lsb = next_step(lsb);
+    }
+    lsb
+}
+
+/// Current LSB and calibration register for a shunt, see
+/// `INA219::calibrate`: the LSB is raised until the calibration register
+/// holds the result.
+pub const fn calibration_for(shunt: ElectricResistance, max_current: ElectricCurrent) -> Option<(ElectricCurrent, u16)> {
+    if shunt <= 0 || max_current <= 0 {
+        return None;
+    }
+    let mut lsb = current_lsb_for(max_current);
+    loop {
+        let cal = calibration_register(lsb, shunt);
+        if cal == 0 {
+            return None;
+        }
+        if cal <= 0xFFFE {
+            // bit 0 of the calibration register is not implemented
+            return Some((lsb, cal as u16 & 0xFFFE));
+        }
+        lsb = next_step(lsb);
+    }
+}
+
+/// Voltage across `shunt` at `current`.
+pub const fn shunt_voltage_at(shunt: ElectricResistance, current: ElectricCurrent) -> ElectricPotential {
+    (shunt as i128 * current as i128 / Ampere as i128) as ElectricPotential
+}
+
+// Current through `shunt` at `voltage`.
+const fn current_at(shunt: ElectricResistance, voltage: ElectricPotential) -> ElectricCurrent {
+    (voltage as i128 * Ampere as i128 / shunt as i128) as ElectricCurrent
+}
+
+// 1, 2, 5, 10, 20, 50, ...
+const fn next_step(lsb: ElectricCurrent) -> ElectricCurrent {
+    let mut decade = 1;
+    while decade * 10 <= lsb {
+        decade *= 10;
+    }
+    match lsb / decade {
+        1 => 2 * decade,
+        2 => 5 * decade,
+        _ => 10 * decade,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::physic::{MicroAmpere, MilliAmpere, MilliOhm, MilliVolt, MilliWatt, Volt, Watt};
+
+    const BOARD: Requirements = Requirements {
+        max_current: 2 * Ampere,
+        resolution: MilliAmpere,
+        max_bus_voltage: 12 * Volt,
+        max_burden: 100 * MilliVolt,
+        max_dissipation: 500 * MilliWatt,
+    };
+
+    // evaluated at compile time
+    const CHOSEN: Result<Design, Unsuitable> = recommend(&BOARD, ESeries::E24);
+
+    #[test]
+    fn matches_the_presets() {
+        assert_eq!(calibration_for(100 * MilliOhm, 2 * Ampere), Some((100 * MicroAmpere, 4096)));
+        assert_eq!(calibration_for(100 * MilliOhm, Ampere), Some((50 * MicroAmpere, 8192)));
+        assert_eq!(current_lsb_for(400 * MilliAmpere), 20 * MicroAmpere);
+        assert_eq!(calibration_for(0, Ampere), None);
+    }
+
+    #[test]
+    fn recommends_largest_fitting_shunt() {
+        assert_eq!(
+            CHOSEN,
+            Ok(Design {
+                shunt: 47 * MilliOhm,
+                gain: Gain::Gain_4_160mV,
+                bus_voltage_range: BusVoltageRange::Range_16V,
+                current_lsb: 100 * MicroAmpere,
+                power_lsb: 2 * MilliWatt,
+                calibration: 8714,
+                // 10 µV / 47 mΩ
+                resolution: 212_765,
+                overflow_current: 3_276_700_000,
+                burden: 94 * MilliVolt,
+                dissipation: 188 * MilliWatt,
+            })
+        );
+        let e96 = recommend(&BOARD, ESeries::E96).unwrap();
+        assert_eq!(e96.shunt, 49_900_000);
+    }
+
+    #[test]
+    fn reports_what_does_not_fit() {
+        let fine = Requirements { resolution: 10 * MicroAmpere, ..BOARD };
+        assert_eq!(recommend(&fine, ESeries::E24), Err(Unsuitable::Resolution));
+        let cold = Requirements { max_dissipation: MilliWatt / 10, max_burden: Volt, ..BOARD };
+        assert_eq!(recommend(&cold, ESeries::E12), Err(Unsuitable::Dissipation));
+        let high = Requirements { max_bus_voltage: 48 * Volt, ..BOARD };
+        assert_eq!(recommend(&high, ESeries::E12), Err(Unsuitable::Requirements));
+
+        assert_eq!(evaluate(Ohm, &BOARD), Err(Unsuitable::ShuntVoltage));
+        assert_eq!(evaluate(100 * MilliOhm, &BOARD), Err(Unsuitable::Burden));
+        let relaxed = Requirements { max_burden: Volt, max_dissipation: Watt, ..BOARD };
+        assert_eq!(evaluate(100 * MilliOhm, &relaxed).map(|d| d.bus_voltage_range), Ok(BusVoltageRange::Range_16V));
+    }
+}
@@ -0,0 +1,258 @@
+//! Efficiency of a DC-DC stage with one INA219 on its input and one on its
+//! output.
+//!
+//! ```ignore
+//! let mut monitor = EfficiencyMonitor::new(input, output).window(8);
+//! let e = monitor.sample_synchronous(&mut delay)?;
+//! if e.plausibility != Plausibility::Plausible { ... }
+//! ```
+//!
+//! Powers are current × bus voltage with the sign of the current, see
+//! [`Measurement::signed_power`], averaged over the last `window` samples.
+
+use alloc::collections::VecDeque;
+
+use embedded_hal::delay::DelayNs;
+use embedded_hal::i2c::I2c;
+
+use crate::error::Error;
+use crate::ina219::{Measurement, INA219};
+use crate::physic::Power;
+
+const PPM: i64 = 1_000_000;
+
+// CNVR polls after the expected conversion time, 100 µs apart.
+const READY_POLLS: u32 = 10;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Plausibility {
+    Plausible,
+    /// More power out than in, beyond the tolerance: a sensor, shunt or
+    /// calibration is off.
+    AboveUnity,
+    /// No power flows into the stage, there is no efficiency to speak of.
+    NoInput,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Efficiency {
+    pub input: Power,
+    pub output: Power,
+    /// Input minus output power.
+    pub loss: Power,
+    /// Output over input power in ppm, `None` without input power.
+    pub efficiency: Option<i64>,
+    pub plausibility: Plausibility,
+}
+
+impl Efficiency {
+    /// Judges `output` against `input`, allowing `tolerance` ppm above
+    /// unity for measurement error.
+    pub fn of(input: Power, output: Power, tolerance: u32) -> Efficiency {
+        let efficiency = if input > 0 {
+            Some((output as i128 * PPM as i128 / input as i128) as i64)
+        } else {
+            None
+        };
+        let plausibility = match efficiency {
+            None => Plausibility::NoInput,
+            Some(ppm) if ppm > PPM + tolerance as i64 => Plausibility::AboveUnity,
+            Some(_) => Plausibility::Plausible,
+        };
+        Efficiency {
+            input,
+            output,
+            loss: input - output,
+            efficiency,
+            plausibility,
+        }
+    }
+
+    pub fn percent(&self) -> Option<f32> {
+        self.efficiency.map(|ppm| ppm as f32 / 10_000.0)
+    }
+}
+
+/// Samples an input and an output INA219 together.
+pub struct EfficiencyMonitor<I2C> {
+    input: INA219<I2C>,
+    output: INA219<I2C>,
+    window: usize,
+    tolerance: u32,
+    history: VecDeque<(Power, Power)>,
+}
+
+impl<I2C: I2c> EfficiencyMonitor<I2C> {
+    /// Pairs two calibrated drivers. Without further settings every sample
+    /// stands alone and nothing above 100 % is plausible.
+    pub fn new(input: INA219<I2C>, output: INA219<I2C>) -> EfficiencyMonitor<I2C> {
+        EfficiencyMonitor {
+            input,
+            output,
+            window: 1,
+            tolerance: 0,
+            history: VecDeque::new(),
+        }
+    }
+
+    /// Averages input and output power over the last `samples`.
+    pub fn window(mut self, samples: usize) -> EfficiencyMonitor<I2C> {
+        self.window = samples.max(1);
+        self
+    }
+
+    /// Efficiency above 100 % by up to `ppm` still counts as plausible.
+    pub fn tolerance(mut self, ppm: u32) -> EfficiencyMonitor<I2C> {
+        self.tolerance = ppm;
+        self
+    }
+
+    pub fn input(&mut self) -> &mut INA219<I2C> {
+        &mut self.input
+    }
+
+    pub fn output(&mut self) -> &mut INA219<I2C> {
+        &mut self.output
+    }
+
+    pub fn release(self) -> (INA219<I2C>, INA219<I2C>) {
+        (self.input, self.output)
+    }
+
+    /// Reads the input, then right away the output, each in one bus
+    /// transaction. Both chips keep converting on their own, so the
+    /// readings can be up to one conversion apart.
+    pub fn sample(&mut self) -> Result<Efficiency, I2C::Error> {
+        let input = self.input.measure()?;
+        let output = self.output.measure()?;
+        Ok(self.add(&input, &output))
+    }
+
+    /// Triggers a conversion on both chips, waits for both to finish and
+    /// reads them, for readings of the same moment. Both chips go back to
+    /// the mode they were in, also on errors, so that `sample` keeps seeing
+    /// fresh conversions.
+    pub fn sample_synchronous<D: DelayNs>(&mut self, delay: &mut D) -> Result<Efficiency, Error<I2C::Error>> {
+        let (input, output) = (self.input.config(), self.output.config());
+        let result = self.convert_synchronous(delay);
+        let restored = restore_config(&mut self.input, input).and(restore_config(&mut self.output, output));
+        let efficiency = result?;
+        restored.map_err(Error::I2c)?;
+        Ok(efficiency)
+    }
+
+    fn convert_synchronous<D: DelayNs>(&mut self, delay: &mut D) -> Result<Efficiency, Error<I2C::Error>> {
+        self.input.trigger().map_err(Error::I2c)?;
+        self.output.trigger().map_err(Error::I2c)?;
+        delay.delay_us(self.input.conversion_time_us().max(self.output.conversion_time_us()));
+        let mut polls = 0;
+        while !(self.input.conversion_ready().map_err(Error::I2c)? && self.output.conversion_ready().map_err(Error::I2c)?) {
+            polls += 1;
+            if polls > READY_POLLS {
+                return Err(Error::ConversionTimeout);
+            }
+            delay.delay_us(100);
+        }
+        let input = self.input.measure().map_err(Error::I2c)?;
+        let output = self.output.measure().map_err(Error::I2c)?;
+        Ok(self.add(&input, &output))
+    }
+
+    /// Adds a pair of readings taken elsewhere to the average.
+    pub fn add(&mut self, input: &Measurement, output: &Measurement) -> Efficiency {
+        if self.history.len() == self.window {
+            self.history.pop_front();
+        }
+        self.history.push_back((input.signed_power(), output.signed_power()));
+        let n = self.history.len() as i64;
+        let (input, output) = self.history.iter().fold((0, 0), |(i, o), &(pi, po)| (i + pi, o + po));
+        Efficiency::of(input / n, output / n, self.tolerance)
+    }
+}
+
+// Undoes `trigger` on a driver that had been configured.
+fn restore_config<I2C: I2c>(ina: &mut INA219<I2C>, config: u16) -> Result<(), I2C::Error> {
+    if config != 0 && ina.config() != config {
+        ina.set_config(config)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ina219::{Calibration, Probe, Register};
+    use crate::physic::{MilliAmpere, MilliWatt, Volt, Watt};
+    use crate::sim::SimulatedINA219;
+
+    struct CountingDelay(u32);
+
+    impl DelayNs for CountingDelay {
+        fn delay_ns(&mut self, ns: u32) {
+            self.0 += ns;
+        }
+    }
+
+    fn ina(shunt_microvolts: i32, bus_millivolts: u32) -> INA219<SimulatedINA219> {
+        let mut ina = INA219::new(SimulatedINA219::with_inputs(shunt_microvolts, bus_millivolts));
+        ina.init(Calibration::Calibration_32V_1A).unwrap();
+        ina
+    }
+
+    #[test]
+    fn back_to_back() {
+        // 12 V × 0.5 A in, 5 V × 1.1 A out
+        let mut monitor = EfficiencyMonitor::new(ina(50_000, 12_000), ina(110_000, 5_000));
+        let e = monitor.sample().unwrap();
+        assert_eq!((e.input, e.output, e.loss), (6 * Watt, 5500 * MilliWatt, 500 * MilliWatt));
+        assert_eq!(e.efficiency, Some(916_666));
+        assert_eq!(e.plausibility, Plausibility::Plausible);
+    }
+
+    #[test]
+    fn averages_over_window() {
+        let mut monitor = EfficiencyMonitor::new(ina(0, 0), ina(0, 0)).window(2);
+        let at = |milliamps: i64, volts: i64| Measurement {
+            current: milliamps * MilliAmpere,
+            bus_voltage: volts * Volt,
+            ..Measurement::default()
+        };
+        monitor.add(&at(500, 12), &at(900, 5));
+        let e = monitor.add(&at(500, 12), &at(1100, 5));
+        // (4.5 W + 5.5 W) / 2 over 6 W
+        assert_eq!((e.input, e.output), (6 * Watt, 5 * Watt));
+        assert_eq!(e.efficiency, Some(833_333));
+        // the 4.5 W sample drops out
+        let e = monitor.add(&at(500, 12), &at(1300, 5));
+        assert_eq!(e.output, 6 * Watt);
+        assert_eq!(e.percent(), Some(100.0));
+        assert_eq!(e.plausibility, Plausibility::Plausible);
+    }
+
+    #[test]
+    fn flags_implausible_readings() {
+        assert_eq!(Efficiency::of(5 * Watt, 5050 * MilliWatt, 0).plausibility, Plausibility::AboveUnity);
+        assert_eq!(Efficiency::of(5 * Watt, 5050 * MilliWatt, 20_000).plausibility, Plausibility::Plausible);
+        assert_eq!(Efficiency::of(0, Watt, 0).plausibility, Plausibility::NoInput);
+        assert_eq!(Efficiency::of(-Watt, 0, 0).efficiency, None);
+    }
+
+    #[test]
+    fn synchronous_conversions() {
+        let mut monitor = EfficiencyMonitor::new(ina(50_000, 12_000), ina(110_000, 5_000));
+        let mut delay = CountingDelay(0);
+        let e = monitor.sample_synchronous(&mut delay).unwrap();
+        assert_eq!(e.efficiency, Some(916_666));
+        // one 12 bit shunt and bus conversion
+        assert_eq!(delay.0, 2 * 532_000);
+        // both chips are back in continuous mode
+        assert_eq!(monitor.input().read_register(Register::Configuration).unwrap(), 0x399F);
+        assert_eq!(monitor.output().read_register(Register::Configuration).unwrap(), 0x399F);
+        assert_eq!(monitor.input().config(), 0x399F);
+        assert_eq!(monitor.input().verify(), Ok(Probe::Configured));
+
+        // and keep converting for plain samples: 5 V x 1.2 A out
+        monitor.output().i2c_mut().set_shunt_voltage_uV(120_000);
+        assert_eq!(monitor.sample().unwrap().output, 6 * Watt);
+    }
+}
@@ -1,5 +1,8 @@
 use core::fmt;
 use alloc::string::String;
+
+use crate::ina219::Gain;
+use crate::physic::{ElectricPotential, ToStringPhysic_potential};
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub enum PhysicError {
     BothPlusAndMinussymbols,
@@ -39,4 +42,41 @@ impl fmt::Display for PhysicError {
             PhysicError::UnknownUnitPrefixErr(ref provide,ref need ) => write!(fmt,"unknown unit prefix; valid prefixes for {} are {}",provide,need),
         }
     }   
-}
\ No newline at end of file
+}
+
+/// Errors of the INA219 driver beyond plain bus errors.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Error<E> {
+    I2c(E),
+    NotDetected(u8),
+    NotIna219(u8),
+    UnexpectedConfiguration(u16),
+    InvalidCalibration,
+    ShuntVoltageOutOfRange(ElectricPotential),
+    GainTooLow(Gain, ElectricPotential),
+    /// A triggered conversion did not finish in time.
+    ConversionTimeout,
+}
+
+impl<E: fmt::Debug> fmt::Display for Error<E> {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::I2c(ref e) => write!(fmt, "i2c error: {:?}", e),
+            Error::NotDetected(address) => write!(fmt, "no device answers at address {:#04x}", address),
+            Error::NotIna219(address) => write!(fmt, "device at address {:#04x} is not an INA219", address),
+            Error::UnexpectedConfiguration(config) => write!(fmt, "unexpected configuration {:#06x}", config),
+            Error::InvalidCalibration => write!(fmt, "no calibration fits the shunt and current"),
+            Error::ShuntVoltageOutOfRange(v) => {
+                write!(fmt, "shunt voltage {} exceeds the 320mV range", v.to_string_physic_potential())
+            }
+            Error::GainTooLow(gain, v) => write!(
+                fmt,
+                "shunt voltage {} exceeds the {} range of {:?}",
+                v.to_string_physic_potential(),
+                gain.full_scale().to_string_physic_potential(),
+                gain
+            ),
+            Error::ConversionTimeout => write!(fmt, "conversion did not finish in time"),
+        }
+    }
+}
@@ -0,0 +1,251 @@
+//! Readings from the Linux `ina2xx` hwmon driver, for boards where the
+//! kernel owns the chip.
+//!
+//! The driver exposes, under `/sys/class/hwmon/hwmonN`:
+//!
+//! | file             | reading                 |
+//! |------------------|-------------------------|
+//! | `in0_input`      | shunt voltage, mV       |
+//! | `in1_input`      | bus voltage, mV         |
+//! | `curr1_input`    | current, mA             |
+//! | `power1_input`   | power, µW               |
+//! | `shunt_resistor` | shunt resistor, µΩ      |
+//!
+//! [`Hwmon`] implements [`PowerSensor`], so the same application code runs
+//! on top of either backend.
+
+use std::format;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::string::{String, ToString};
+use std::vec::Vec;
+
+use crate::ina219::{Measurement, PowerMonitor};
+use crate::physic::{ElectricResistance, MicroOhm, MicroWatt, MilliAmpere, MilliVolt};
+use crate::sensor::{Averaging, PowerSensor};
+
+pub const HWMON_ROOT: &str = "/sys/class/hwmon";
+
+const DRIVER_NAMES: [&str; 1] = ["ina219"];
+
+/// One hwmon device bound to the `ina2xx` driver.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Hwmon {
+    dir: PathBuf,
+}
+
+impl Hwmon {
+    /// Uses the hwmon directory `dir`, e.g. `/sys/class/hwmon/hwmon2`.
+    pub fn new<P: AsRef<Path>>(dir: P) -> Hwmon {
+        Hwmon {
+            dir: dir.as_ref().to_path_buf(),
+        }
+    }
+
+    /// Finds the INA219 at `address` in [`HWMON_ROOT`].
+    pub fn find(address: u8) -> io::Result<Hwmon> {
+        Hwmon::all_in(HWMON_ROOT)?
+            .into_iter()
+            .find(|hwmon| hwmon.address() == Some(address))
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("no ina219 at {:#04x} in {}", address, HWMON_ROOT),
+                )
+            })
+    }
+
+    /// Every INA219 hwmon device below `root`, in directory order.
+    pub fn all_in<P: AsRef<Path>>(root: P) -> io::Result<Vec<Hwmon>> {
+        let mut dirs = Vec::new();
+        for entry in fs::read_dir(root)? {
+            let dir = entry?.path();
+            if let Ok(name) = fs::read_to_string(dir.join("name")) {
+                if DRIVER_NAMES.contains(&name.trim()) {
+                    dirs.push(dir);
+                }
+            }
+        }
+        dirs.sort();
+        Ok(dirs.into_iter().map(Hwmon::new).collect())
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// I2C address taken from the `device` link, which points at the
+    /// `<bus>-<address>` client directory, e.g. `1-0040`.
+    pub fn address(&self) -> Option<u8> {
+        let device = fs::read_link(self.dir.join("device")).ok()?;
+        let name = device.file_name()?.to_str()?;
+        let (_, address) = name.split_once('-')?;
+        u8::from_str_radix(address, 16).ok()
+    }
+
+    pub fn shunt(&self) -> io::Result<ElectricResistance> {
+        Ok(self.read("shunt_resistor")? * MicroOhm)
+    }
+
+    /// Tells the driver which shunt is fitted; needs write access to sysfs.
+    pub fn set_shunt(&mut self, shunt: ElectricResistance) -> io::Result<()> {
+        fs::write(self.dir.join("shunt_resistor"), (shunt / MicroOhm).to_string())
+    }
+
+    fn read(&self, file: &str) -> io::Result<i64> {
+        let path = self.dir.join(file);
+        let text = fs::read_to_string(&path)?;
+        text.trim().parse().map_err(|_| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{}: {:?} is not a number", path.display(), text.trim()),
+            )
+        })
+    }
+
+    fn unsupported(what: &str) -> io::Error {
+        io::Error::new(io::ErrorKind::Unsupported, String::from(what) + " is controlled by the kernel driver")
+    }
+}
+
+impl PowerSensor for Hwmon {
+    type Error = io::Error;
+
+    fn bus_voltage_V(&mut self) -> io::Result<f32> {
+        Ok(self.read("in1_input")? as f32 / 1000.0)
+    }
+
+    fn shunt_voltage_mV(&mut self) -> io::Result<f32> {
+        Ok(self.read("in0_input")? as f32)
+    }
+
+    fn current_mA(&mut self) -> io::Result<f32> {
+        Ok(self.read("curr1_input")? as f32)
+    }
+
+    fn power_mW(&mut self) -> io::Result<f32> {
+        Ok(self.read("power1_input")? as f32 / 1000.0)
+    }
+
+    fn sense(&mut self) -> io::Result<PowerMonitor> {
+        Ok(self.measure()?.into())
+    }
+
+    fn measure(&mut self) -> io::Result<Measurement> {
+        Ok(Measurement {
+            shunt_voltage: self.read("in0_input")? * MilliVolt,
+            bus_voltage: self.read("in1_input")? * MilliVolt,
+            current: self.read("curr1_input")? * MilliAmpere,
+            power: self.read("power1_input")? * MicroWatt,
+        })
+    }
+
+    fn set_averaging(&mut self, _averaging: Averaging) -> io::Result<()> {
+        Err(Hwmon::unsupported("averaging"))
+    }
+
+    fn power_down(&mut self) -> io::Result<()> {
+        Err(Hwmon::unsupported("power down"))
+    }
+
+    fn wake(&mut self) -> io::Result<()> {
+        Err(Hwmon::unsupported("power down"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::physic::{MilliOhm, Volt, Watt};
+
+    struct FakeSysfs {
+        root: PathBuf,
+    }
+
+    impl FakeSysfs {
+        fn new(test: &str) -> FakeSysfs {
+            let root = std::env::temp_dir().join(format!("ina219-hwmon-{}-{}", test, std::process::id()));
+            let _ = fs::remove_dir_all(&root);
+            fs::create_dir_all(&root).unwrap();
+            FakeSysfs { root }
+        }
+
+        fn device(&self, hwmon: &str, name: &str, client: &str, files: &[(&str, &str)]) -> PathBuf {
+            let dir = self.root.join(hwmon);
+            fs::create_dir_all(&dir).unwrap();
+            fs::write(dir.join("name"), format!("{}\n", name)).unwrap();
+            std::os::unix::fs::symlink(format!("../../../{}", client), dir.join("device")).unwrap();
+            for (file, value) in files {
+                fs::write(dir.join(file), format!("{}\n", value)).unwrap();
+            }
+            dir
+        }
+    }
+
+    impl Drop for FakeSysfs {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.root);
+        }
+    }
+
+    const READINGS: [(&str, &str); 5] = [
+        ("in0_input", "150"),
+        ("in1_input", "12000"),
+        ("curr1_input", "1500"),
+        ("power1_input", "18000000"),
+        ("shunt_resistor", "100000"),
+    ];
+
+    #[test]
+    fn reads_like_the_i2c_driver() {
+        let sysfs = FakeSysfs::new("read");
+        let dir = sysfs.device("hwmon0", "ina219", "1-0040", &READINGS);
+        let mut hwmon = Hwmon::new(dir);
+        assert_eq!(hwmon.address(), Some(0x40));
+        assert_eq!(hwmon.shunt().unwrap(), 100 * MilliOhm);
+        assert_eq!(
+            hwmon.measure().unwrap(),
+            Measurement {
+                shunt_voltage: 150 * MilliVolt,
+                bus_voltage: 12 * Volt,
+                current: 1500 * MilliAmpere,
+                power: 18 * Watt,
+            }
+        );
+        let pm = hwmon.sense().unwrap();
+        assert_eq!((pm.Shunt, pm.Voltage, pm.Current, pm.Power), (150.0, 12.0, 1500.0, 18000.0));
+        assert_eq!(hwmon.bus_voltage_V().unwrap(), 12.0);
+        assert_eq!(hwmon.power_mW().unwrap(), 18000.0);
+    }
+
+    #[test]
+    fn finds_ina219_devices() {
+        let sysfs = FakeSysfs::new("find");
+        sysfs.device("hwmon0", "cpu_thermal", "thermal", &[]);
+        sysfs.device("hwmon2", "ina219", "1-0041", &READINGS);
+        sysfs.device("hwmon1", "ina219", "1-0040", &READINGS);
+        let found = Hwmon::all_in(&sysfs.root).unwrap();
+        let addresses: Vec<_> = found.iter().map(Hwmon::address).collect();
+        assert_eq!(addresses, [Some(0x40), Some(0x41)]);
+    }
+
+    #[test]
+    fn reports_bad_files() {
+        let sysfs = FakeSysfs::new("bad");
+        let dir = sysfs.device("hwmon0", "ina219", "1-0040", &[("in1_input", "garbage")]);
+        let mut hwmon = Hwmon::new(dir);
+        assert_eq!(hwmon.bus_voltage_V().unwrap_err().kind(), io::ErrorKind::InvalidData);
+        assert_eq!(hwmon.current_mA().unwrap_err().kind(), io::ErrorKind::NotFound);
+        assert_eq!(hwmon.power_down().unwrap_err().kind(), io::ErrorKind::Unsupported);
+    }
+
+    #[test]
+    fn writes_shunt() {
+        let sysfs = FakeSysfs::new("shunt");
+        let dir = sysfs.device("hwmon0", "ina219", "1-0040", &READINGS);
+        let mut hwmon = Hwmon::new(dir);
+        hwmon.set_shunt(10 * MilliOhm).unwrap();
+        assert_eq!(hwmon.shunt().unwrap(), 10 * MilliOhm);
+    }
+}
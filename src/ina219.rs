@@ -6,14 +6,31 @@
 #![allow(unused_must_use)]
 
 
-use core::convert::TryInto;
 use core::fmt;
 
-use embedded_hal::i2c::I2c;
+use alloc::vec::Vec;
+
+use embedded_hal::delay::DelayNs;
+use embedded_hal::i2c::{Error as I2cError, ErrorKind, I2c, NoAcknowledgeSource, Operation};
 use enum_iterator::Sequence;
 
+use crate::design::{calibration_for, shunt_voltage_at};
+use crate::error::Error;
+use crate::physic::{ElectricCurrent, ElectricPotential, ElectricResistance, Power, Temperature, Ampere, Celsius, MicroAmpere, MicroVolt, MilliAmpere, MilliOhm, MilliVolt, MilliWatt, Volt};
+use crate::sensor::{Averaging, PowerSensor};
+
 pub const INA219_ADDR: u8 = 0x40;
 
+/** A0/A1 strapping selects one of 16 addresses **/
+pub const INA219_ADDR_FIRST: u8 = 0x40;
+pub const INA219_ADDR_LAST: u8 = 0x4F;
+
+
+/** reset bit, self-clearing **/
+const INA219_CONFIG_RESET: u16 = 0x8000;
+
+/** configuration after power-on or reset **/
+const INA219_CONFIG_POWER_ON_DEFAULT: u16 = 0x399F;
 
 /** mask for bus voltage range **/
 const INA219_CONFIG_BVOLTAGERANGE_MASK: u16 = 0x2000; // Bus Voltage Range Mask
@@ -124,6 +141,125 @@ impl PowerMonitor {
             Power: power,
         }
     }
+
+    /// Current × bus voltage in mW, negative when the current is. `Power`
+    /// comes from the unsigned power register.
+    pub fn signed_power(&self) -> f32 {
+        self.Current * self.Voltage
+    }
+
+    pub fn direction(&self, deadband_mA: f32) -> Direction {
+        if self.Current > deadband_mA.abs() {
+            Direction::Charging
+        } else if self.Current < -deadband_mA.abs() {
+            Direction::Discharging
+        } else {
+            Direction::Idle
+        }
+    }
+}
+
+/// Which way current flows through the shunt. Positive current, from IN+
+/// to IN-, is charging: wire IN- towards the battery, or use `reversed`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    Charging,
+    Discharging,
+    /// Within the deadband around zero.
+    Idle,
+}
+
+impl Direction {
+    /// Direction for `current`, idle within ±`deadband`.
+    pub fn of(current: ElectricCurrent, deadband: ElectricCurrent) -> Direction {
+        if current > deadband.abs() {
+            Direction::Charging
+        } else if current < -deadband.abs() {
+            Direction::Discharging
+        } else {
+            Direction::Idle
+        }
+    }
+
+    /// The same flow seen through a shunt wired the other way round.
+    pub fn reversed(self) -> Direction {
+        match self {
+            Direction::Charging => Direction::Discharging,
+            Direction::Discharging => Direction::Charging,
+            Direction::Idle => Direction::Idle,
+        }
+    }
+}
+
+impl From<Measurement> for PowerMonitor {
+    fn from(m: Measurement) -> PowerMonitor {
+        PowerMonitor::new(
+            nano_as_f32(m.shunt_voltage, MilliVolt),
+            nano_as_f32(m.bus_voltage, Volt),
+            nano_as_f32(m.current, MilliAmpere),
+            nano_as_f32(m.power, MilliWatt),
+        )
+    }
+}
+
+/// Addresses of all chips on the bus that look like an INA219.
+pub fn scan<I2C: I2c>(i2c: &mut I2C) -> Result<Vec<u8>, I2C::Error> {
+    let mut found = Vec::new();
+    for address in INA219_ADDR_FIRST..=INA219_ADDR_LAST {
+        // like i2cdetect, any error on the first read means nothing is
+        // there: adapters report a missing address in different ways
+        let config = match read_from(i2c, address, Register::Configuration) {
+            Ok(config) => config,
+            Err(_) => continue,
+        };
+        if matches_ina219(i2c, address, config)? {
+            found.push(address);
+        }
+    }
+    Ok(found)
+}
+
+fn looks_like_ina219<I2C: I2c>(i2c: &mut I2C, address: u8) -> Result<bool, I2C::Error> {
+    let config = read_from(i2c, address, Register::Configuration)?;
+    matches_ina219(i2c, address, config)
+}
+
+// Bits that read back as zero on an INA219: configuration bit 14, bus
+// voltage bit 2 and calibration bit 0. A device that reads all zeros has no
+// configuration register at all.
+fn matches_ina219<I2C: I2c>(i2c: &mut I2C, address: u8, config: u16) -> Result<bool, I2C::Error> {
+    let bus = read_from(i2c, address, Register::BusVoltage)?;
+    let calibration = read_from(i2c, address, Register::Calibration)?;
+    Ok(config != 0 && config & 0x4000 == 0 && bus & 0x0004 == 0 && calibration & 0x0001 == 0)
+}
+
+fn read_from<I2C: I2c>(i2c: &mut I2C, address: u8, register: u8) -> Result<u16, I2C::Error> {
+    let mut buf = [0u8; 2];
+    i2c.write_read(address, &[register], &mut buf)?;
+    Ok(u16::from_be_bytes(buf))
+}
+
+fn is_address_nack<E: I2cError>(e: &E) -> bool {
+    e.kind() == ErrorKind::NoAcknowledge(NoAcknowledgeSource::Address)
+}
+
+fn nack_as_missing<E: I2cError>(e: E, address: u8) -> Error<E> {
+    if is_address_nack(&e) {
+        Error::NotDetected(address)
+    } else {
+        Error::I2c(e)
+    }
+}
+
+// Bus voltage register: 13 bit value in 4 mV steps above CNVR and OVF.
+fn bus_voltage_mV(value: u16) -> i16 {
+    ((value >> 3) * 4) as i16
+}
+
+// Keeps the integer part exact before adding the fraction, so that e.g.
+// 12 V does not come out as 12.000001 V.
+fn nano_as_f32(value: i64, unit: i64) -> f32 {
+    (value / unit) as f32 + (value % unit) as f32 / unit as f32
 }
 
 impl fmt::Display for PowerMonitor {
@@ -147,6 +283,49 @@ impl fmt::Debug for PowerMonitor {
     }
 }
 
+/// A full sample in integer nano-units, without any floating point rounding.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Measurement {
+    pub shunt_voltage: ElectricPotential,
+    pub bus_voltage: ElectricPotential,
+    pub current: ElectricCurrent,
+    pub power: Power,
+}
+
+impl Measurement {
+    /// Current × bus voltage, negative when the current is. `power` follows
+    /// the unsigned power register.
+    pub fn signed_power(&self) -> Power {
+        (self.current as i128 * self.bus_voltage as i128 / Volt as i128) as Power
+    }
+
+    pub fn direction(&self, deadband: ElectricCurrent) -> Direction {
+        Direction::of(self.current, deadband)
+    }
+}
+
+/// Raw contents of the data registers, as read in a single bus transaction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct RegisterSnapshot {
+    pub shunt_voltage: u16,
+    pub bus_voltage: u16,
+    pub power: u16,
+    pub current: u16,
+    pub calibration: u16,
+}
+
+impl RegisterSnapshot {
+    /// Conversion Ready flag of the bus voltage register.
+    pub fn conversion_ready(&self) -> bool {
+        self.bus_voltage & 0x0002 != 0
+    }
+
+    /// Math Overflow flag of the bus voltage register.
+    pub fn overflow(&self) -> bool {
+        self.bus_voltage & 0x0001 != 0
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Sequence)]
 pub enum Calibration {
     Calibration_32V_2A,
@@ -154,6 +333,134 @@ pub enum Calibration {
     Calibration_16V_400mA,
 }
 
+/// Full scale range of the bus voltage ADC (BRNG).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Sequence)]
+pub enum BusVoltageRange {
+    Range_16V,
+    Range_32V,
+}
+
+impl BusVoltageRange {
+    pub const fn full_scale(self) -> ElectricPotential {
+        match self {
+            BusVoltageRange::Range_16V => 16 * Volt,
+            BusVoltageRange::Range_32V => 32 * Volt,
+        }
+    }
+
+    /// Smaller range that holds `bus_voltage`.
+    pub const fn for_bus_voltage(bus_voltage: ElectricPotential) -> Option<BusVoltageRange> {
+        if bus_voltage <= 16 * Volt {
+            Some(BusVoltageRange::Range_16V)
+        } else if bus_voltage <= 32 * Volt {
+            Some(BusVoltageRange::Range_32V)
+        } else {
+            None
+        }
+    }
+
+    fn bits(self) -> u16 {
+        match self {
+            BusVoltageRange::Range_16V => INA219_CONFIG_BVOLTAGERANGE_16V,
+            BusVoltageRange::Range_32V => INA219_CONFIG_BVOLTAGERANGE_32V,
+        }
+    }
+
+    fn from_config(config: u16) -> BusVoltageRange {
+        if config & INA219_CONFIG_BVOLTAGERANGE_MASK == INA219_CONFIG_BVOLTAGERANGE_32V {
+            BusVoltageRange::Range_32V
+        } else {
+            BusVoltageRange::Range_16V
+        }
+    }
+}
+
+/// PGA gain, which sets the shunt voltage range.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Sequence)]
+pub enum Gain {
+    Gain_1_40mV,
+    Gain_2_80mV,
+    Gain_4_160mV,
+    Gain_8_320mV,
+}
+
+impl Gain {
+    /// Largest shunt voltage the gain can measure.
+    pub const fn full_scale(self) -> ElectricPotential {
+        match self {
+            Gain::Gain_1_40mV => 40 * MilliVolt,
+            Gain::Gain_2_80mV => 80 * MilliVolt,
+            Gain::Gain_4_160mV => 160 * MilliVolt,
+            Gain::Gain_8_320mV => 320 * MilliVolt,
+        }
+    }
+
+    /// Smallest gain, i.e. best resolution, that holds `shunt_voltage`.
+    pub const fn for_shunt_voltage(shunt_voltage: ElectricPotential) -> Option<Gain> {
+        let v = shunt_voltage.abs();
+        if v <= 40 * MilliVolt {
+            Some(Gain::Gain_1_40mV)
+        } else if v <= 80 * MilliVolt {
+            Some(Gain::Gain_2_80mV)
+        } else if v <= 160 * MilliVolt {
+            Some(Gain::Gain_4_160mV)
+        } else if v <= 320 * MilliVolt {
+            Some(Gain::Gain_8_320mV)
+        } else {
+            None
+        }
+    }
+
+    fn bits(self) -> u16 {
+        match self {
+            Gain::Gain_1_40mV => INA219_CONFIG_GAIN_1_40MV,
+            Gain::Gain_2_80mV => INA219_CONFIG_GAIN_2_80MV,
+            Gain::Gain_4_160mV => INA219_CONFIG_GAIN_4_160MV,
+            Gain::Gain_8_320mV => INA219_CONFIG_GAIN_8_320MV,
+        }
+    }
+
+    fn from_config(config: u16) -> Gain {
+        match config & INA219_CONFIG_GAIN_MASK {
+            INA219_CONFIG_GAIN_1_40MV => Gain::Gain_1_40mV,
+            INA219_CONFIG_GAIN_2_80MV => Gain::Gain_2_80mV,
+            INA219_CONFIG_GAIN_4_160MV => Gain::Gain_4_160mV,
+            _ => Gain::Gain_8_320mV,
+        }
+    }
+}
+
+// Shunt ADC conversion time for the 9 to 12 bit settings and for averaging
+// 1 to 128 samples.
+const CONVERSION_TIME_US: [u32; 4] = [84, 148, 276, 532];
+const AVERAGED_CONVERSION_TIME_US: [u32; 8] = [532, 1060, 2130, 4260, 8510, 17020, 34050, 68100];
+
+fn shunt_conversion_time_us(config: u16) -> u32 {
+    adc_conversion_time_us((config & INA219_CONFIG_SADCRES_MASK) >> 3)
+}
+
+fn bus_conversion_time_us(config: u16) -> u32 {
+    adc_conversion_time_us((config & INA219_CONFIG_BADCRES_MASK) >> 7)
+}
+
+fn adc_conversion_time_us(code: u16) -> u32 {
+    let code = code as usize;
+    if code & 0x8 == 0 {
+        CONVERSION_TIME_US[code & 0x3]
+    } else {
+        AVERAGED_CONVERSION_TIME_US[code & 0x7]
+    }
+}
+
+// Division rounding halves away from zero.
+fn div_round(n: i64, d: i64) -> i64 {
+    if (n < 0) == (d < 0) {
+        (n + d / 2) / d
+    } else {
+        (n - d / 2) / d
+    }
+}
+
 pub struct Register;
 
 impl Register {
@@ -165,25 +472,171 @@ impl Register {
     pub const Calibration: u8 = 0x05;
 }
 
+/// A current reading taken while an external meter measured `reference`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CalibrationPoint {
+    pub reference: ElectricCurrent,
+    /// Current as read by the chip with the tare applied, as every later
+    /// reading will be, but without `current_offset`, which
+    /// `field_calibrate` fits anew.
+    pub measured: ElectricCurrent,
+    /// Calibration register value the reading was taken with.
+    pub calibration: u16,
+}
+
+/// Per-board calibration, as produced by `INA219::field_calibrate`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct CalibrationRecord {
+    pub shunt: ElectricResistance,
+    pub current_lsb: ElectricCurrent,
+    pub calibration: u16,
+    /// Added to every current reading.
+    pub current_offset: ElectricCurrent,
+    /// Shunt voltage at zero load, see `INA219::tare`.
+    pub shunt_offset: ElectricPotential,
+    pub max_current: ElectricCurrent,
+    /// Shunt temperature coefficient, ppm/°C.
+    pub tempco: i32,
+    /// Temperature the shunt has its nominal value at.
+    pub reference_temperature: Temperature,
+    /// Shunt resistor tolerance, ppm.
+    pub shunt_tolerance: u32,
+}
+
+impl CalibrationRecord {
+    /// Length of `to_bytes`.
+    pub const SIZE: usize = 58;
+
+    /// Little-endian shunt, current LSB, calibration, current offset, shunt
+    /// offset, maximum current, tempco, reference temperature and shunt
+    /// tolerance.
+    pub fn to_bytes(&self) -> [u8; CalibrationRecord::SIZE] {
+        let mut bytes = [0u8; CalibrationRecord::SIZE];
+        bytes[0..8].copy_from_slice(&self.shunt.to_le_bytes());
+        bytes[8..16].copy_from_slice(&self.current_lsb.to_le_bytes());
+        bytes[16..18].copy_from_slice(&self.calibration.to_le_bytes());
+        bytes[18..26].copy_from_slice(&self.current_offset.to_le_bytes());
+        bytes[26..34].copy_from_slice(&self.shunt_offset.to_le_bytes());
+        bytes[34..42].copy_from_slice(&self.max_current.to_le_bytes());
+        bytes[42..46].copy_from_slice(&self.tempco.to_le_bytes());
+        bytes[46..54].copy_from_slice(&self.reference_temperature.to_le_bytes());
+        bytes[54..58].copy_from_slice(&self.shunt_tolerance.to_le_bytes());
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Option<CalibrationRecord> {
+        if bytes.len() != CalibrationRecord::SIZE {
+            return None;
+        }
+        let i64_at = |at: usize| {
+            let mut b = [0u8; 8];
+            b.copy_from_slice(&bytes[at..at + 8]);
+            i64::from_le_bytes(b)
+        };
+        Some(CalibrationRecord {
+            shunt: i64_at(0),
+            current_lsb: i64_at(8),
+            calibration: u16::from_le_bytes([bytes[16], bytes[17]]),
+            current_offset: i64_at(18),
+            shunt_offset: i64_at(26),
+            max_current: i64_at(34),
+            tempco: i32::from_le_bytes([bytes[42], bytes[43], bytes[44], bytes[45]]),
+            reference_temperature: i64_at(46),
+            shunt_tolerance: u32::from_le_bytes([bytes[54], bytes[55], bytes[56], bytes[57]]),
+        })
+    }
+}
+
+/// What `probe` found at the driver's address.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Probe {
+    /// The configuration register holds the value this driver wrote.
+    Configured,
+    /// The configuration register is at its power-on default.
+    PowerOnDefault,
+    /// The chip answers with some other configuration.
+    Unknown(u16),
+}
+
 pub struct INA219<I2C> {
     i2c: I2C,
+    address: u8,
     cal_value: u16,
-    currentDivider_mA: i16,
-    powerMultiplier_mW: f32,
+    config: u16,
+    powered_down: bool,
+    resets: u32,
+    // a zero reading was already checked for a reset
+    zero_checked: bool,
+    shunt: ElectricResistance,
+    max_current: ElectricCurrent,
+    current_lsb: ElectricCurrent,
+    power_lsb: Power,
+    current_offset: ElectricCurrent,
+    shunt_offset: i16,
+    tempco: i32,
+    reference_temperature: Temperature,
+    shunt_tolerance: u32,
 }
 
 impl<I2C: I2c> INA219<I2C>
 {
     pub fn new(i2c: I2C) -> INA219<I2C> {
+        INA219::with_address(i2c, INA219_ADDR)
+    }
+
+    pub fn with_address(i2c: I2C, address: u8) -> INA219<I2C> {
         INA219 {
             i2c,
+            address,
             cal_value: 0,
-            currentDivider_mA: 0,
-            powerMultiplier_mW: 0.0,
+            config: 0,
+            powered_down: false,
+            resets: 0,
+            zero_checked: false,
+            shunt: 0,
+            max_current: 0,
+            current_lsb: 0,
+            power_lsb: 0,
+            current_offset: 0,
+            shunt_offset: 0,
+            tempco: 0,
+            reference_temperature: 25 * Celsius,
+            shunt_tolerance: 0,
+        }
+    }
+
+    /// Takes over a chip someone else configured, e.g. a bootloader, without
+    /// writing to it. The current and power LSBs follow from the calibration
+    /// register and `shunt`: Current_LSB = 0.04096 / (Cal * RSHUNT). With the
+    /// calibration register at zero, current and power read zero. The
+    /// maximum current is what the configured gain can measure.
+    pub fn attach(i2c: I2C, address: u8, shunt: ElectricResistance) -> Result<INA219<I2C>, Error<I2C::Error>> {
+        if shunt <= 0 {
+            return Err(Error::InvalidCalibration);
+        }
+        let mut ina = INA219::with_address(i2c, address);
+        ina.config = ina.read(Register::Configuration).map_err(|e| nack_as_missing(e, address))?;
+        ina.cal_value = ina.read(Register::Calibration).map_err(Error::I2c)?;
+        ina.powered_down = ina.config & INA219_CONFIG_MODE_MASK == INA219_CONFIG_MODE_POWERDOWN;
+        ina.shunt = shunt;
+        if ina.cal_value != 0 {
+            let divisor = ina.cal_value as i128 * shunt as i128;
+            ina.current_lsb = ((40_960_000_000_000_000i128 + divisor / 2) / divisor) as ElectricCurrent;
         }
+        ina.power_lsb = 20 * ina.current_lsb;
+        ina.max_current = ina.measurable_current();
+        Ok(ina)
+    }
+
+    // The largest current both the shunt ADC at the configured gain and the
+    // current register can represent, for calibrations that do not say.
+    fn measurable_current(&self) -> ElectricCurrent {
+        let adc_limit = (self.gain().full_scale() as i128 * Ampere as i128 / self.shunt as i128) as ElectricCurrent;
+        adc_limit.min(self.current_lsb * 32767)
     }
 
     pub fn init(&mut self, cal: Calibration) -> Result<(), I2C::Error> {
+        self.current_offset = 0;
         match cal {
             Calibration::Calibration_32V_2A => { self.setCalibration_32V_2A() }
             Calibration::Calibration_32V_1A => { self.setCalibration_32V_1A() }
@@ -255,30 +708,18 @@ impl<I2C: I2c> INA219<I2C>
         // MaximumPower = 3.2 * 32V
         // MaximumPower = 102.4W
 
-        // Set multipliers to convert raw current/power values
-        self.currentDivider_mA = 10; // Current LSB = 100uA per bit (1000/100 = 10)
-        self.powerMultiplier_mW = 2.0; // Power LSB = 1mW per bit (2/1)
-
-        let buf = self.cal_value.to_be_bytes();
-        self.i2c.write(
-            INA219_ADDR,
-            &[Register::Calibration, buf[0], buf[1]],
-        );
+        // Set LSBs to convert raw current/power values
+        self.shunt = 100 * MilliOhm;
+        self.max_current = 2 * Ampere;
+        self.current_lsb = 100 * MicroAmpere; // Current LSB = 100uA per bit
+        self.power_lsb = 20 * self.current_lsb; // Power LSB = 2mW per bit
 
-        let config = INA219_CONFIG_BVOLTAGERANGE_32V |
+        self.config = INA219_CONFIG_BVOLTAGERANGE_32V |
             INA219_CONFIG_GAIN_8_320MV | INA219_CONFIG_BADCRES_12BIT |
             INA219_CONFIG_SADCRES_12BIT_1S_532US |
             INA219_CONFIG_MODE_SANDBVOLT_CONTINUOUS;
 
-        let buf = config.to_be_bytes();
-        self.i2c.write(
-            INA219_ADDR,
-            &[
-                Register::Configuration,
-                buf[0],
-                buf[1],
-            ],
-        )
+        self.write_calibration_and_config()
     }
 
     fn setCalibration_32V_1A(&mut self) -> Result<(), I2C::Error> {
@@ -349,30 +790,18 @@ impl<I2C: I2c> INA219<I2C>
         // MaximumPower = 41.94176W
 
 
-        // Set multipliers to convert raw current/power values
-        self.currentDivider_mA = 25; // Current LSB = 100uA per bit (1000/100 = 10)
-        self.powerMultiplier_mW = 0.8; // Power LSB = 1mW per bit (2/1)
+        // Set LSBs to convert raw current/power values
+        self.shunt = 100 * MilliOhm;
+        self.max_current = Ampere;
+        self.current_lsb = 40 * MicroAmpere; // Current LSB = 40uA per bit
+        self.power_lsb = 20 * self.current_lsb; // Power LSB = 0.8mW per bit
 
-        let buf = self.cal_value.to_be_bytes();
-        self.i2c.write(
-            INA219_ADDR,
-            &[Register::Calibration, buf[0], buf[1]],
-        );
-
-        let config = INA219_CONFIG_BVOLTAGERANGE_32V |
+        self.config = INA219_CONFIG_BVOLTAGERANGE_32V |
             INA219_CONFIG_GAIN_8_320MV | INA219_CONFIG_BADCRES_12BIT |
             INA219_CONFIG_SADCRES_12BIT_1S_532US |
             INA219_CONFIG_MODE_SANDBVOLT_CONTINUOUS;
 
-        let buf = config.to_be_bytes();
-        self.i2c.write(
-            INA219_ADDR,
-            &[
-                Register::Configuration,
-                buf[0],
-                buf[1],
-            ],
-        )
+        self.write_calibration_and_config()
     }
 
     fn setCalibration_16V_400mA(&mut self) -> Result<(), I2C::Error> {
@@ -442,99 +871,1424 @@ impl<I2C: I2c> INA219<I2C>
         // MaximumPower = 0.4 * 16V
         // MaximumPower = 6.4W
 
-        // Set multipliers to convert raw current/power values
-        self.currentDivider_mA = 20; // Current LSB = 100uA per bit (1000/100 = 10)
-        self.powerMultiplier_mW = 1.0; // Power LSB = 1mW per bit (2/1)
+        // Set LSBs to convert raw current/power values
+        self.shunt = 100 * MilliOhm;
+        self.max_current = 400 * MilliAmpere;
+        self.current_lsb = 50 * MicroAmpere; // Current LSB = 50uA per bit
+        self.power_lsb = 20 * self.current_lsb; // Power LSB = 1mW per bit
 
-        let buf = self.cal_value.to_be_bytes();
-        self.i2c.write(
-            INA219_ADDR,
-            &[Register::Calibration, buf[0], buf[1]],
-        );
-
-        let config = INA219_CONFIG_BVOLTAGERANGE_16V |
+        self.config = INA219_CONFIG_BVOLTAGERANGE_16V |
             INA219_CONFIG_GAIN_1_40MV | INA219_CONFIG_BADCRES_12BIT |
             INA219_CONFIG_SADCRES_12BIT_1S_532US |
             INA219_CONFIG_MODE_SANDBVOLT_CONTINUOUS;
 
-        let buf = config.to_be_bytes();
-        self.i2c.write(
-            INA219_ADDR,
-            &[
-                Register::Configuration,
-                buf[0],
-                buf[1],
-            ],
-        )
+        self.write_calibration_and_config()
+    }
+
+
+    /// Calibrates for any shunt resistor and the largest current expected
+    /// through it, following the datasheet procedure used by the presets:
+    /// the PGA gain is the smallest range that holds `shunt * max_current`,
+    /// the current LSB is the first 1-2-5 step above `max_current / 32767`
+    /// and Cal = trunc(0.04096 / (Current_LSB * RSHUNT)). Bus voltage range,
+    /// ADC settings and mode are kept when already configured.
+    pub fn calibrate(&mut self, shunt: ElectricResistance, max_current: ElectricCurrent) -> Result<(), Error<I2C::Error>> {
+        if shunt <= 0 || max_current <= 0 {
+            return Err(Error::InvalidCalibration);
+        }
+        let shunt_voltage = shunt_voltage_at(shunt, max_current);
+        let gain = match Gain::for_shunt_voltage(shunt_voltage) {
+            Some(gain) => gain,
+            None => return Err(Error::ShuntVoltageOutOfRange(shunt_voltage)),
+        };
+        let (current_lsb, cal_value) = match calibration_for(shunt, max_current) {
+            Some(c) => c,
+            None => return Err(Error::InvalidCalibration),
+        };
+
+        self.shunt = shunt;
+        self.max_current = max_current;
+        self.current_offset = 0;
+        self.cal_value = cal_value;
+        self.current_lsb = current_lsb;
+        self.power_lsb = 20 * current_lsb;
+        self.config = (self.base_config() & !INA219_CONFIG_GAIN_MASK) | gain.bits();
+        self.write_calibration_and_config().map_err(Error::I2c)
+    }
+
+    // Configuration to modify, 32V, 12 bit and continuous when nothing was
+    // configured yet.
+    fn base_config(&self) -> u16 {
+        if self.config == 0 {
+            INA219_CONFIG_BVOLTAGERANGE_32V | INA219_CONFIG_GAIN_8_320MV | INA219_CONFIG_BADCRES_12BIT |
+                INA219_CONFIG_SADCRES_12BIT_1S_532US |
+                INA219_CONFIG_MODE_SANDBVOLT_CONTINUOUS
+        } else {
+            self.config
+        }
+    }
+
+    pub fn bus_voltage_range(&self) -> BusVoltageRange {
+        BusVoltageRange::from_config(self.base_config())
+    }
+
+    /// Selects the 16 V or 32 V bus voltage range, keeping every other setting.
+    pub fn set_bus_voltage_range(&mut self, range: BusVoltageRange) -> Result<(), I2C::Error> {
+        self.config = (self.base_config() & !INA219_CONFIG_BVOLTAGERANGE_MASK) | range.bits();
+        self.write_config()
+    }
+
+    pub fn gain(&self) -> Gain {
+        Gain::from_config(self.base_config())
+    }
+
+    /// Selects any PGA gain, independent of the bus voltage range. Once
+    /// calibrated, the gain has to hold the shunt voltage at the maximum
+    /// current. The calibration register does not depend on the gain and is
+    /// kept, including a field calibrated one.
+    pub fn set_gain(&mut self, gain: Gain) -> Result<(), Error<I2C::Error>> {
+        if self.shunt > 0 && self.current_lsb > 0 {
+            let shunt_voltage = shunt_voltage_at(self.shunt, self.max_current);
+            if shunt_voltage > gain.full_scale() {
+                return Err(Error::GainTooLow(gain, shunt_voltage));
+            }
+        }
+        self.config = (self.base_config() & !INA219_CONFIG_GAIN_MASK) | gain.bits();
+        self.write_config().map_err(Error::I2c)
+    }
+
+    /// Sets the whole configuration register, e.g. restored from storage.
+    pub fn set_config(&mut self, config: u16) -> Result<(), I2C::Error> {
+        self.config = config & !INA219_CONFIG_RESET;
+        self.powered_down = config & INA219_CONFIG_MODE_MASK == INA219_CONFIG_MODE_POWERDOWN;
+        self.write(Register::Configuration, self.config)
+    }
+
+    // Writes the configuration unless powered down, in which case `wake`
+    // writes it.
+    fn write_config(&mut self) -> Result<(), I2C::Error> {
+        if self.powered_down {
+            return Ok(());
+        }
+        let config = self.config;
+        self.write(Register::Configuration, config)
+    }
+
+    /// Reads the current while an external meter measures `reference`, for
+    /// `field_calibrate`.
+    pub fn calibration_point(&mut self, reference: ElectricCurrent) -> Result<CalibrationPoint, I2C::Error> {
+        let measured = self.current_raw()? as i64 * self.current_lsb;
+        Ok(CalibrationPoint {
+            reference,
+            measured,
+            calibration: self.cal_value,
+        })
+    }
+
+    /// Trims the calibration register against an external meter. One point
+    /// applies the datasheet's corrected full scale calibration,
+    /// Cal = trunc(Cal * MeasShuntCurrent / INA219_Current); with two or
+    /// more points at different currents the gain and an offset are fitted
+    /// by least squares. The resulting record can be stored and restored
+    /// with `apply_calibration_record`.
+    pub fn field_calibrate(&mut self, points: &[CalibrationPoint]) -> Result<CalibrationRecord, Error<I2C::Error>> {
+        if points.is_empty() || self.cal_value == 0 || points.iter().any(|p| p.calibration == 0) {
+            return Err(Error::InvalidCalibration);
+        }
+        let cal = self.cal_value as i128;
+        let n = points.len() as i128;
+        let (mut sx, mut sy, mut sxx, mut sxy) = (0i128, 0i128, 0i128, 0i128);
+        for p in points {
+            // as if read with the current calibration
+            let x = p.measured as i128 * cal / p.calibration as i128;
+            let y = p.reference as i128;
+            sx += x;
+            sy += y;
+            sxx += x * x;
+            sxy += x * y;
+        }
+        let spread = n * sxx - sx * sx;
+        let fit_offset = n > 1 && spread != 0;
+        let (num, den) = if fit_offset { (n * sxy - sx * sy, spread) } else { (sy, sx) };
+        if den == 0 {
+            return Err(Error::InvalidCalibration);
+        }
+        let cal_value = match cal * num / den {
+            c @ 2..=0xFFFF => c as u16 & 0xFFFE,
+            _ => return Err(Error::InvalidCalibration),
+        };
+        // offset against the truncated calibration actually programmed
+        let current_offset = if fit_offset {
+            ((sy - sx * cal_value as i128 / cal) / n) as ElectricCurrent
+        } else {
+            0
+        };
+        self.cal_value = cal_value;
+        self.current_offset = current_offset;
+        self.write(Register::Calibration, cal_value).map_err(Error::I2c)?;
+        Ok(self.calibration_record())
+    }
+
+    pub fn calibration_record(&self) -> CalibrationRecord {
+        CalibrationRecord {
+            shunt: self.shunt,
+            current_lsb: self.current_lsb,
+            calibration: self.cal_value,
+            current_offset: self.current_offset,
+            shunt_offset: self.shunt_offset(),
+            max_current: self.max_current,
+            tempco: self.tempco,
+            reference_temperature: self.reference_temperature,
+            shunt_tolerance: self.shunt_tolerance,
+        }
+    }
+
+    /// Restores a stored calibration, keeping the configuration register.
+    pub fn apply_calibration_record(&mut self, record: &CalibrationRecord) -> Result<(), Error<I2C::Error>> {
+        if record.shunt <= 0 || record.current_lsb <= 0 || record.calibration == 0 {
+            return Err(Error::InvalidCalibration);
+        }
+        self.shunt = record.shunt;
+        self.current_lsb = record.current_lsb;
+        self.power_lsb = 20 * record.current_lsb;
+        self.max_current = if record.max_current > 0 {
+            record.max_current
+        } else {
+            self.measurable_current()
+        };
+        self.cal_value = record.calibration;
+        self.current_offset = record.current_offset;
+        self.set_shunt_offset(record.shunt_offset);
+        self.tempco = record.tempco;
+        self.reference_temperature = record.reference_temperature;
+        self.shunt_tolerance = record.shunt_tolerance;
+        self.write(Register::Calibration, record.calibration).map_err(Error::I2c)
+    }
+
+    /// Added to every current reading, from field calibration.
+    pub fn current_offset(&self) -> ElectricCurrent {
+        self.current_offset
+    }
+
+    /// Zeroes the shunt voltage: with no load connected, averages `samples`
+    /// readings of the shunt register, one conversion time apart, and from
+    /// then on subtracts the result from shunt voltage, current and power.
+    pub fn tare<D: DelayNs>(&mut self, samples: u16, delay: &mut D) -> Result<ElectricPotential, I2C::Error> {
+        let samples = samples.max(1) as i32;
+        let wait = shunt_conversion_time_us(self.config);
+        let mut sum = 0i32;
+        for i in 0..samples {
+            if i > 0 {
+                delay.delay_us(wait);
+            }
+            sum += self.read(Register::ShuntVoltage)? as i16 as i32;
+        }
+        self.shunt_offset = div_round(sum as i64, samples as i64) as i16;
+        Ok(self.shunt_offset())
+    }
+
+    /// Shunt voltage subtracted from every reading, see `tare`.
+    pub fn shunt_offset(&self) -> ElectricPotential {
+        self.shunt_offset as i64 * 10 * MicroVolt
+    }
+
+    /// Restores a stored tare offset, rounded to the 10 µV register step.
+    pub fn set_shunt_offset(&mut self, offset: ElectricPotential) {
+        let steps = div_round(offset, 10 * MicroVolt);
+        self.shunt_offset = steps.clamp(i16::MIN as i64, i16::MAX as i64) as i16;
+    }
+
+    fn corrected_shunt(&self, raw: u16) -> i16 {
+        (raw as i16).saturating_sub(self.shunt_offset)
+    }
+
+    // The current register is Shunt * Cal / 4096, so is the tare offset.
+    fn corrected_current(&self, raw: u16) -> i16 {
+        let offset = div_round(self.shunt_offset as i64 * self.cal_value as i64, 4096);
+        (raw as i16 as i64 - offset).clamp(i16::MIN as i64, i16::MAX as i64) as i16
+    }
+
+    // Power has to be recomputed when the power register does not see a
+    // correction.
+    fn corrects_power(&self) -> bool {
+        self.shunt_offset != 0 || self.current_offset != 0
+    }
+
+    /// Sets the shunt's temperature coefficient, in ppm/°C, and the
+    /// temperature its nominal resistance is specified at. The `_at` reading
+    /// methods then correct current and power for the shunt temperature.
+    pub fn set_tempco(&mut self, ppm_per_celsius: i32, reference: Temperature) {
+        self.tempco = ppm_per_celsius;
+        self.reference_temperature = reference;
+    }
+
+    /// Shunt temperature coefficient in ppm/°C, 0 when not compensated.
+    pub fn tempco(&self) -> i32 {
+        self.tempco
+    }
+
+    pub fn reference_temperature(&self) -> Temperature {
+        self.reference_temperature
+    }
+
+    /// Tolerance of the fitted shunt in ppm, e.g. 10_000 for 1 %, used by
+    /// `uncertainty::Model`.
+    pub fn set_shunt_tolerance(&mut self, ppm: u32) {
+        self.shunt_tolerance = ppm;
+    }
+
+    pub fn shunt_tolerance(&self) -> u32 {
+        self.shunt_tolerance
+    }
+
+    /// Corrects `value`, a current or a power computed with the nominal
+    /// shunt, for a shunt at `temperature`:
+    /// value * 1 / (1 + tempco * (T - Tref)), all in integers.
+    pub fn compensate(&self, value: i64, temperature: Temperature) -> i64 {
+        if self.tempco == 0 {
+            return value;
+        }
+        // 1e6 ppm times 1e9 nano degrees
+        const ONE: i128 = 1_000_000 * Celsius as i128;
+        let delta = temperature as i128 - self.reference_temperature as i128;
+        let den = ONE + self.tempco as i128 * delta;
+        if den <= 0 {
+            return value;
+        }
+        let num = value as i128 * ONE;
+        let half = if num < 0 { -den / 2 } else { den / 2 };
+        ((num + half) / den) as i64
+    }
+
+    /// Shunt resistance the calibration was computed for.
+    pub fn shunt(&self) -> ElectricResistance {
+        self.shunt
+    }
+
+    /// Largest expected current the calibration was computed for.
+    pub fn max_current(&self) -> ElectricCurrent {
+        self.max_current
     }
 
+    /// Value written to the calibration register.
+    pub fn calibration_value(&self) -> u16 {
+        self.cal_value
+    }
+
+    /// Value written to the configuration register.
+    pub fn config(&self) -> u16 {
+        self.config
+    }
+
+    fn write_calibration_and_config(&mut self) -> Result<(), I2C::Error> {
+        let cal_value = self.cal_value;
+        self.write(Register::Calibration, cal_value)?;
+        self.powered_down = false;
+        let config = self.config;
+        self.write(Register::Configuration, config)
+    }
+
+    /// Averages `averaging` 12-bit samples for both the bus and the shunt ADC.
+    pub fn set_averaging(&mut self, averaging: Averaging) -> Result<(), I2C::Error> {
+        let (badc, sadc) = match averaging {
+            Averaging::Samples1 => (INA219_CONFIG_BADCRES_12BIT, INA219_CONFIG_SADCRES_12BIT_1S_532US),
+            Averaging::Samples2 => (INA219_CONFIG_BADCRES_12BIT_2S_1060US, INA219_CONFIG_SADCRES_12BIT_2S_1060US),
+            Averaging::Samples4 => (INA219_CONFIG_BADCRES_12BIT_4S_2130US, INA219_CONFIG_SADCRES_12BIT_4S_2130US),
+            Averaging::Samples8 => (INA219_CONFIG_BADCRES_12BIT_8S_4260US, INA219_CONFIG_SADCRES_12BIT_8S_4260US),
+            Averaging::Samples16 => (INA219_CONFIG_BADCRES_12BIT_16S_8510US, INA219_CONFIG_SADCRES_12BIT_16S_8510US),
+            Averaging::Samples32 => (INA219_CONFIG_BADCRES_12BIT_32S_17MS, INA219_CONFIG_SADCRES_12BIT_32S_17MS),
+            Averaging::Samples64 => (INA219_CONFIG_BADCRES_12BIT_64S_34MS, INA219_CONFIG_SADCRES_12BIT_64S_34MS),
+            Averaging::Samples128 => (INA219_CONFIG_BADCRES_12BIT_128S_69MS, INA219_CONFIG_SADCRES_12BIT_128S_69MS),
+        };
+        self.config = (self.base_config() & !(INA219_CONFIG_BADCRES_MASK | INA219_CONFIG_SADCRES_MASK)) | badc | sadc;
+        self.write_config()
+    }
+
+    /// Puts the chip into power-down mode, keeping the configuration for `wake`.
+    pub fn power_down(&mut self) -> Result<(), I2C::Error> {
+        let config = (self.base_config() & !INA219_CONFIG_MODE_MASK) | INA219_CONFIG_MODE_POWERDOWN;
+        self.write(Register::Configuration, config)?;
+        self.powered_down = true;
+        Ok(())
+    }
+
+    /// Leaves power-down mode and restores the configured operating mode.
+    pub fn wake(&mut self) -> Result<(), I2C::Error> {
+        let config = self.base_config();
+        self.write(Register::Configuration, config)?;
+        self.powered_down = false;
+        Ok(())
+    }
+
+    /// Starts one shunt and bus voltage conversion in triggered mode, e.g.
+    /// on several chips at once for readings taken at the same moment. The
+    /// chip stays in triggered mode, also across other settings, until
+    /// `set_config` selects another mode.
+    pub fn trigger(&mut self) -> Result<(), I2C::Error> {
+        self.config = (self.base_config() & !INA219_CONFIG_MODE_MASK) | INA219_CONFIG_MODE_SANDBVOLT_TRIGGERED;
+        self.powered_down = false;
+        let config = self.config;
+        self.write(Register::Configuration, config)
+    }
+
+    /// The CNVR flag: a conversion finished since the power register was
+    /// last read.
+    pub fn conversion_ready(&mut self) -> Result<bool, I2C::Error> {
+        Ok(self.read(Register::BusVoltage)? & 0x0002 != 0)
+    }
+
+    /// Time for one shunt and one bus voltage conversion with the configured
+    /// resolution and averaging.
+    pub fn conversion_time_us(&self) -> u32 {
+        let config = self.base_config();
+        shunt_conversion_time_us(config) + bus_conversion_time_us(config)
+    }
 
     pub fn shunt_voltage_raw(&mut self) -> Result<i16, I2C::Error> {
         let value = self.read(Register::ShuntVoltage)?;
-        Ok(value as i16)
+        Ok(self.corrected_shunt(value))
     }
 
     pub fn voltage_raw(&mut self) -> Result<i16, I2C::Error> {
         let value = self.read(Register::BusVoltage)?;
-        Ok(((value >> 3) * 4) as i16)
+        Ok(bus_voltage_mV(value))
     }
 
-    pub fn power_raw(&mut self) -> Result<i16, I2C::Error> {
-        //need to calibrate first
-        let buf = self.cal_value.to_be_bytes();
-        self.i2c.write(
-            INA219_ADDR,
-            &[Register::Calibration, buf[0], buf[1]],
-        );
-
+    /// Power in power LSBs. With a shunt offset set it is recomputed from
+    /// the tared current, as the chip's own register does not see the tare;
+    /// like `current_raw` it leaves out `current_offset`, which only the
+    /// readings in physical units apply.
+    pub fn power_raw(&mut self) -> Result<u16, I2C::Error> {
+        if self.shunt_offset != 0 {
+            // Power = Current * BusVoltage / 5000, with the tared current
+            let current = self.current_raw()?;
+            let bus = self.read(Register::BusVoltage)? >> 3;
+            return Ok((current.unsigned_abs() as u32 * bus as u32 / 5000).min(0xFFFF) as u16);
+        }
         let value = self.read(Register::Power)?;
-        Ok(value as i16)
+        self.check_zero(value)?;
+        Ok(value)
     }
 
+    /// Current in current LSBs, tared but without `current_offset`.
     pub fn current_raw(&mut self) -> Result<i16, I2C::Error> {
-        //need to calibrate first
-        let buf = self.cal_value.to_be_bytes();
-        self.i2c.write(
-            INA219_ADDR,
-            &[Register::Calibration, buf[0], buf[1]],
-        );
         let value = self.read(Register::Current)?;
-        Ok(value as i16)
+        self.check_zero(value)?;
+        Ok(self.corrected_current(value))
     }
 
-    pub fn getShuntVoltage_mV(&mut self) -> Result<f32, I2C::Error> {
+    // A chip that lost its calibration reads zero current and power. An idle
+    // rail does too, so only the first of a run of zero readings is checked;
+    // `measure` sees the calibration register with every snapshot anyway.
+    fn check_zero(&mut self, value: u16) -> Result<(), I2C::Error> {
+        if value != 0 {
+            self.zero_checked = false;
+        } else if !self.zero_checked {
+            self.zero_checked = true;
+            self.detect_reset()?;
+        }
+        Ok(())
+    }
+
+    /// Reads any register, for diagnostics.
+    pub fn read_register(&mut self, register: u8) -> Result<u16, I2C::Error> {
+        self.read(register)
+    }
+
+    /// Writes any register, for diagnostics. The driver's stored
+    /// configuration and calibration are left untouched, so a later reset
+    /// recovery restores them.
+    pub fn write_register(&mut self, register: u8, value: u16) -> Result<(), I2C::Error> {
+        self.write(register, value)
+    }
+
+    pub fn address(&self) -> u8 {
+        self.address
+    }
+
+    #[cfg(test)]
+    pub(crate) fn i2c_mut(&mut self) -> &mut I2C {
+        &mut self.i2c
+    }
+
+    /// Gives the bus back, e.g. to take the log out of a recording transport.
+    pub fn release(self) -> I2C {
+        self.i2c
+    }
+
+    /// Reads the configuration register and compares it with the value this
+    /// driver configured and with the power-on default. The mode bits are not
+    /// compared, `power_down` and `trigger` change them.
+    pub fn probe(&mut self) -> Result<Probe, I2C::Error> {
+        let config = self.read(Register::Configuration)?;
+        if self.config != 0 && config & !INA219_CONFIG_MODE_MASK == self.config & !INA219_CONFIG_MODE_MASK {
+            Ok(Probe::Configured)
+        } else if config == INA219_CONFIG_POWER_ON_DEFAULT {
+            Ok(Probe::PowerOnDefault)
+        } else {
+            Ok(Probe::Unknown(config))
+        }
+    }
+
+    /// Checks that an INA219 answers at the driver's address and holds either
+    /// the configured or the power-on configuration.
+    pub fn verify(&mut self) -> Result<Probe, Error<I2C::Error>> {
+        let address = self.address;
+        let probe = self.probe().map_err(|e| nack_as_missing(e, address))?;
+        if !looks_like_ina219(&mut self.i2c, address).map_err(Error::I2c)? {
+            return Err(Error::NotIna219(address));
+        }
+        match probe {
+            Probe::Unknown(config) => Err(Error::UnexpectedConfiguration(config)),
+            _ => Ok(probe),
+        }
+    }
+
+    /// Number of chip resets, e.g. brown-outs, detected so far.
+    pub fn reset_count(&self) -> u32 {
+        self.resets
+    }
+
+    /// Checks that the chip still holds the calibration and restores it if
+    /// not. Returns `true` when the chip was found back at its power-on
+    /// defaults, in which case the full configuration is restored too.
+    pub fn detect_reset(&mut self) -> Result<bool, I2C::Error> {
+        let calibration = self.read(Register::Calibration)?;
+        self.recover(calibration)
+    }
+
+    /// Resets the chip through the RST bit, then restores the stored
+    /// configuration and calibration.
+    pub fn reset(&mut self) -> Result<(), I2C::Error> {
+        self.write(Register::Configuration, INA219_CONFIG_RESET)?;
+        self.restore()
+    }
+
+    fn recover(&mut self, calibration: u16) -> Result<bool, I2C::Error> {
+        // bit 0 of the calibration register always reads back as zero
+        if calibration == self.cal_value & 0xFFFE {
+            return Ok(false);
+        }
+        if calibration == 0 && self.read(Register::Configuration)? == INA219_CONFIG_POWER_ON_DEFAULT {
+            self.resets = self.resets.saturating_add(1);
+            self.restore()?;
+            return Ok(true);
+        }
+        let cal_value = self.cal_value;
+        self.write(Register::Calibration, cal_value)?;
+        Ok(false)
+    }
+
+    fn restore(&mut self) -> Result<(), I2C::Error> {
+        // nothing was configured yet
+        if self.config == 0 {
+            return Ok(());
+        }
+        let cal_value = self.cal_value;
+        self.write(Register::Calibration, cal_value)?;
+        let config = if self.powered_down {
+            (self.config & !INA219_CONFIG_MODE_MASK) | INA219_CONFIG_MODE_POWERDOWN
+        } else {
+            self.config
+        };
+        self.write(Register::Configuration, config)
+    }
+
+    /// Current per bit of the current register.
+    pub fn current_lsb(&self) -> ElectricCurrent {
+        self.current_lsb
+    }
+
+    /// Power per bit of the power register, always 20 times the current LSB.
+    pub fn power_lsb(&self) -> Power {
+        self.power_lsb
+    }
+
+    pub fn shunt_voltage(&mut self) -> Result<ElectricPotential, I2C::Error> {
         let value = self.shunt_voltage_raw()?;
-        Ok(value as f32 * 0.01)
+        Ok(value as i64 * 10 * MicroVolt)
     }
 
-    pub fn getBusVoltage_V(&mut self) -> Result<f32, I2C::Error> {
+    pub fn bus_voltage(&mut self) -> Result<ElectricPotential, I2C::Error> {
         let value = self.voltage_raw()?;
-        Ok(value as f32 * 0.001)
+        Ok(value as i64 * MilliVolt)
     }
 
-    pub fn getCurrent_mA(&mut self) -> Result<f32, I2C::Error> {
+    pub fn current(&mut self) -> Result<ElectricCurrent, I2C::Error> {
         let value = self.current_raw()?;
-        Ok((value / self.currentDivider_mA) as f32)
+        Ok(value as i64 * self.current_lsb + self.current_offset)
     }
 
-    pub fn getPower_mW(&mut self) -> Result<f32, I2C::Error> {
+    pub fn power(&mut self) -> Result<Power, I2C::Error> {
+        if self.corrects_power() {
+            // the power register does not see the offsets, see convert
+            return Ok(self.measure()?.power);
+        }
         let value = self.power_raw()?;
-        Ok(value as f32 * self.powerMultiplier_mW)
+        Ok(value as i64 * self.power_lsb)
+    }
+
+    /// Reads shunt voltage, bus voltage, power, current and calibration in
+    /// one bus transaction. The calibration register is rewritten only when
+    /// it no longer holds the configured value, e.g. after a brown-out (see
+    /// `detect_reset`); the current and power of that snapshot are then stale.
+    pub fn snapshot(&mut self) -> Result<RegisterSnapshot, I2C::Error> {
+        let mut shunt = [0u8; 2];
+        let mut bus = [0u8; 2];
+        let mut power = [0u8; 2];
+        let mut current = [0u8; 2];
+        let mut calibration = [0u8; 2];
+        self.i2c.transaction(
+            self.address,
+            &mut [
+                Operation::Write(&[Register::ShuntVoltage]),
+                Operation::Read(&mut shunt),
+                Operation::Write(&[Register::BusVoltage]),
+                Operation::Read(&mut bus),
+                Operation::Write(&[Register::Power]),
+                Operation::Read(&mut power),
+                Operation::Write(&[Register::Current]),
+                Operation::Read(&mut current),
+                Operation::Write(&[Register::Calibration]),
+                Operation::Read(&mut calibration),
+            ],
+        )?;
+        let snapshot = RegisterSnapshot {
+            shunt_voltage: u16::from_be_bytes(shunt),
+            bus_voltage: u16::from_be_bytes(bus),
+            power: u16::from_be_bytes(power),
+            current: u16::from_be_bytes(current),
+            calibration: u16::from_be_bytes(calibration),
+        };
+
+        self.recover(snapshot.calibration)?;
+        Ok(snapshot)
+    }
+
+    /// Converts a snapshot with this driver's calibration. With a tare or
+    /// field calibration offset, power is recomputed from the corrected
+    /// current and the bus voltage instead of read from the power register.
+    pub fn convert(&self, snapshot: &RegisterSnapshot) -> Measurement {
+        let bus_voltage = bus_voltage_mV(snapshot.bus_voltage) as i64 * MilliVolt;
+        let current = self.corrected_current(snapshot.current) as i64 * self.current_lsb + self.current_offset;
+        let power = if !self.corrects_power() {
+            snapshot.power as i64 * self.power_lsb
+        } else {
+            (current.unsigned_abs() as i128 * bus_voltage as i128 / Volt as i128) as Power
+        };
+        Measurement {
+            shunt_voltage: self.corrected_shunt(snapshot.shunt_voltage) as i64 * 10 * MicroVolt,
+            bus_voltage,
+            current,
+            power,
+        }
+    }
+
+    pub fn measure(&mut self) -> Result<Measurement, I2C::Error> {
+        let snapshot = self.snapshot()?;
+        Ok(self.convert(&snapshot))
+    }
+
+    /// Like `convert`, with current and power corrected for a shunt at
+    /// `temperature`, see `set_tempco`.
+    pub fn convert_at(&self, snapshot: &RegisterSnapshot, temperature: Temperature) -> Measurement {
+        let m = self.convert(snapshot);
+        Measurement {
+            current: self.compensate(m.current, temperature),
+            power: self.compensate(m.power, temperature),
+            ..m
+        }
+    }
+
+    /// Reads all registers and corrects for a shunt at `temperature`, e.g.
+    /// from a thermistor or another sensor next to the shunt.
+    pub fn measure_at(&mut self, temperature: Temperature) -> Result<Measurement, I2C::Error> {
+        let snapshot = self.snapshot()?;
+        Ok(self.convert_at(&snapshot, temperature))
+    }
+
+    pub fn current_at(&mut self, temperature: Temperature) -> Result<ElectricCurrent, I2C::Error> {
+        let current = self.current()?;
+        Ok(self.compensate(current, temperature))
+    }
+
+    pub fn power_at(&mut self, temperature: Temperature) -> Result<Power, I2C::Error> {
+        let power = self.power()?;
+        Ok(self.compensate(power, temperature))
+    }
+
+    pub fn getShuntVoltage_mV(&mut self) -> Result<f32, I2C::Error> {
+        let value = self.shunt_voltage()?;
+        Ok(nano_as_f32(value, MilliVolt))
+    }
+
+    pub fn getBusVoltage_V(&mut self) -> Result<f32, I2C::Error> {
+        let value = self.bus_voltage()?;
+        Ok(nano_as_f32(value, Volt))
+    }
+
+    pub fn getCurrent_mA(&mut self) -> Result<f32, I2C::Error> {
+        let value = self.current()?;
+        Ok(nano_as_f32(value, MilliAmpere))
+    }
+
+    pub fn getPower_mW(&mut self) -> Result<f32, I2C::Error> {
+        let value = self.power()?;
+        Ok(nano_as_f32(value, MilliWatt))
+    }
+
+    pub fn getSignedPower_mW(&mut self) -> Result<f32, I2C::Error> {
+        let value = self.signed_power()?;
+        Ok(nano_as_f32(value, MilliWatt))
+    }
+
+    /// Power with the sign of the current, from one snapshot.
+    pub fn signed_power(&mut self) -> Result<Power, I2C::Error> {
+        Ok(self.measure()?.signed_power())
+    }
+
+    /// Reads the current and tells which way it flows, idle within
+    /// ±`deadband`.
+    pub fn direction(&mut self, deadband: ElectricCurrent) -> Result<Direction, I2C::Error> {
+        Ok(Direction::of(self.current()?, deadband))
     }
 
     pub fn sense(&mut self) -> Result<PowerMonitor, I2C::Error> {
-        let shunt = self.getShuntVoltage_mV().unwrap();
-        let voltage = self.getBusVoltage_V().unwrap();
-        let current = self.getCurrent_mA().unwrap();
-        let power = self.getPower_mW().unwrap();
-        let pm = PowerMonitor::new(shunt, voltage, current, power);
-        Ok(pm)
+        let m = self.measure()?;
+        Ok(m.into())
     }
 
     fn read(&mut self, register: u8) -> Result<u16, I2C::Error> {
         let mut buf: [u8; 2] = [0x00; 2];
-        self.i2c.write(INA219_ADDR, &[register])?;
-        self.i2c.read(INA219_ADDR, &mut buf)?;
+        self.i2c.write_read(self.address, &[register], &mut buf)?;
         Ok(u16::from_be_bytes(buf))
     }
+
+    fn write(&mut self, register: u8, value: u16) -> Result<(), I2C::Error> {
+        let buf = value.to_be_bytes();
+        self.i2c.write(self.address, &[register, buf[0], buf[1]])
+    }
+}
+
+impl<I2C: I2c> PowerSensor for INA219<I2C> {
+    type Error = I2C::Error;
+
+    fn bus_voltage_V(&mut self) -> Result<f32, I2C::Error> {
+        self.getBusVoltage_V()
+    }
+
+    fn shunt_voltage_mV(&mut self) -> Result<f32, I2C::Error> {
+        self.getShuntVoltage_mV()
+    }
+
+    fn current_mA(&mut self) -> Result<f32, I2C::Error> {
+        self.getCurrent_mA()
+    }
+
+    fn power_mW(&mut self) -> Result<f32, I2C::Error> {
+        self.getPower_mW()
+    }
+
+    fn sense(&mut self) -> Result<PowerMonitor, I2C::Error> {
+        INA219::sense(self)
+    }
+
+    fn measure(&mut self) -> Result<Measurement, I2C::Error> {
+        INA219::measure(self)
+    }
+
+    fn set_averaging(&mut self, averaging: Averaging) -> Result<(), I2C::Error> {
+        INA219::set_averaging(self, averaging)
+    }
+
+    fn power_down(&mut self) -> Result<(), I2C::Error> {
+        INA219::power_down(self)
+    }
+
+    fn wake(&mut self) -> Result<(), I2C::Error> {
+        INA219::wake(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::physic::{MicroWatt, Ohm, Watt};
+    use crate::sim::{SimError, SimulatedINA219};
+    use enum_iterator::all;
+    use proptest::prelude::*;
+
+    struct Golden {
+        cal: Calibration,
+        config: u16,
+        cal_value: u16,
+        shunt_uV: i32,
+        bus_mV: u32,
+        current_raw: i16,
+        power_raw: u16,
+        current_mA: f32,
+        power_mW: f32,
+    }
+
+    // expected register contents follow the datasheet math:
+    // Current = Shunt * Cal / 4096, Power = Current * Bus / 5000
+    fn golden(cal: Calibration) -> Golden {
+        match cal {
+            Calibration::Calibration_32V_2A => Golden {
+                cal,
+                config: 0x399F,
+                cal_value: 4096,
+                shunt_uV: 150_000,
+                bus_mV: 12_000,
+                current_raw: 15000,
+                power_raw: 9000,
+                current_mA: 1500.0,
+                power_mW: 18000.0,
+            },
+            Calibration::Calibration_32V_1A => Golden {
+                cal,
+                config: 0x399F,
+                cal_value: 10240,
+                shunt_uV: 50_000,
+                bus_mV: 12_000,
+                current_raw: 12500,
+                power_raw: 7500,
+                current_mA: 500.0,
+                power_mW: 6000.0,
+            },
+            Calibration::Calibration_16V_400mA => Golden {
+                cal,
+                config: 0x019F,
+                cal_value: 8192,
+                shunt_uV: 25_000,
+                bus_mV: 5_000,
+                current_raw: 5000,
+                power_raw: 1250,
+                current_mA: 250.0,
+                power_mW: 1250.0,
+            },
+        }
+    }
+
+    fn ina(cal: Calibration, shunt_uV: i32, bus_mV: u32) -> INA219<SimulatedINA219> {
+        let mut ina = INA219::new(SimulatedINA219::with_inputs(shunt_uV, bus_mV));
+        ina.init(cal).unwrap();
+        ina
+    }
+
+    #[test]
+    fn calibration_presets() {
+        for cal in all::<Calibration>() {
+            let g = golden(cal);
+            let mut ina = ina(g.cal.clone(), g.shunt_uV, g.bus_mV);
+            assert_eq!(ina.i2c.register(Register::Configuration), g.config, "{:?}", g.cal);
+            assert_eq!(ina.i2c.register(Register::Calibration), g.cal_value, "{:?}", g.cal);
+            assert_eq!(ina.current_raw().unwrap(), g.current_raw, "{:?}", g.cal);
+            assert_eq!(ina.power_raw().unwrap(), g.power_raw, "{:?}", g.cal);
+            assert_eq!(ina.getCurrent_mA().unwrap(), g.current_mA, "{:?}", g.cal);
+            assert_eq!(ina.getPower_mW().unwrap(), g.power_mW, "{:?}", g.cal);
+            assert_eq!(ina.getShuntVoltage_mV().unwrap(), g.shunt_uV as f32 / 1000.0, "{:?}", g.cal);
+            assert_eq!(ina.getBusVoltage_V().unwrap(), g.bus_mV as f32 / 1000.0, "{:?}", g.cal);
+        }
+    }
+
+    #[test]
+    fn current_keeps_sub_milliamp_precision() {
+        // 90uV across 0.1 Ohm, 40uA LSB: raw 22 is 0.88 mA, not 0 mA
+        let mut ina = ina(Calibration::Calibration_32V_1A, 90, 5_000);
+        assert_eq!(ina.current_raw().unwrap(), 22);
+        assert!((ina.getCurrent_mA().unwrap() - 0.88).abs() < 1e-6);
+    }
+
+    #[test]
+    fn power_register_is_unsigned() {
+        // 3 A at 30 V is 45000 LSBs of 2 mW, past i16::MAX
+        let mut ina = ina(Calibration::Calibration_32V_2A, 300_000, 30_000);
+        assert_eq!(ina.power_raw().unwrap(), 45000);
+        assert_eq!(ina.power().unwrap(), 90 * Watt);
+        assert_eq!(ina.getPower_mW().unwrap(), 90000.0);
+    }
+
+    #[test]
+    fn nano_unit_readings() {
+        let g = golden(Calibration::Calibration_32V_1A);
+        let mut ina = ina(g.cal, g.shunt_uV, g.bus_mV);
+        assert_eq!(ina.current_lsb(), 40 * MicroAmpere);
+        assert_eq!(ina.power_lsb(), 800 * MicroWatt);
+        let m = ina.measure().unwrap();
+        assert_eq!(m, Measurement {
+            shunt_voltage: 50 * MilliVolt,
+            bus_voltage: 12 * Volt,
+            current: 500 * MilliAmpere,
+            power: 6 * Watt,
+        });
+    }
+
+    #[test]
+    fn measure_is_a_single_transaction() {
+        let g = golden(Calibration::Calibration_32V_2A);
+        let mut ina = ina(g.cal, g.shunt_uV, g.bus_mV);
+        let before = ina.i2c.transactions();
+        let snapshot = ina.snapshot().unwrap();
+        assert_eq!(ina.i2c.transactions(), before + 1);
+        assert_eq!(snapshot, RegisterSnapshot {
+            shunt_voltage: 15000,
+            bus_voltage: (3000 << 3) | 0x0002,
+            power: 9000,
+            current: 15000,
+            calibration: 4096,
+        });
+        assert!(snapshot.conversion_ready());
+        assert!(!snapshot.overflow());
+        // reading the power register cleared CNVR
+        assert!(!ina.snapshot().unwrap().conversion_ready());
+        assert_eq!(ina.i2c.transactions(), before + 2);
+    }
+
+    #[test]
+    fn snapshot_restores_lost_calibration() {
+        let g = golden(Calibration::Calibration_16V_400mA);
+        let mut ina = ina(g.cal, g.shunt_uV, g.bus_mV);
+        ina.i2c.set_register(Register::Calibration, 0);
+        let before = ina.i2c.transactions();
+        let snapshot = ina.snapshot().unwrap();
+        assert_eq!(snapshot.calibration, 0);
+        // snapshot, configuration check, calibration write
+        assert_eq!(ina.i2c.transactions(), before + 3);
+        assert_eq!(ina.i2c.register(Register::Calibration), 8192);
+        assert_eq!(ina.measure().unwrap().current, 250 * MilliAmpere);
+    }
+
+    #[test]
+    fn brown_out_is_detected_and_recovered() {
+        let g = golden(Calibration::Calibration_32V_1A);
+        let mut ina = ina(g.cal, g.shunt_uV, g.bus_mV);
+        ina.set_averaging(Averaging::Samples8).unwrap();
+        let config = ina.i2c.register(Register::Configuration);
+        assert_eq!(ina.reset_count(), 0);
+
+        ina.i2c.power_cycle();
+        let snapshot = ina.snapshot().unwrap();
+        assert_eq!(snapshot.calibration, 0);
+        assert_eq!(ina.reset_count(), 1);
+        assert_eq!(ina.i2c.register(Register::Configuration), config);
+        assert_eq!(ina.i2c.register(Register::Calibration), 10240);
+        assert_eq!(ina.getCurrent_mA().unwrap(), 500.0);
+
+        ina.i2c.power_cycle();
+        assert_eq!(ina.current_raw().unwrap(), 0);
+        assert_eq!(ina.reset_count(), 2);
+        assert_eq!(ina.current_raw().unwrap(), 12500);
+    }
+
+    #[test]
+    fn brown_out_while_powered_down() {
+        let mut ina = ina(Calibration::Calibration_32V_2A, 0, 5_000);
+        ina.power_down().unwrap();
+        ina.i2c.power_cycle();
+        assert!(ina.detect_reset().unwrap());
+        assert_eq!(ina.i2c.register(Register::Configuration), 0x3998);
+    }
+
+    #[test]
+    fn calibration_overwritten_without_reset() {
+        let mut ina = ina(Calibration::Calibration_32V_2A, 10_000, 5_000);
+        ina.i2c.set_register(Register::Calibration, 2048);
+        assert!(!ina.detect_reset().unwrap());
+        assert_eq!(ina.reset_count(), 0);
+        assert_eq!(ina.i2c.register(Register::Calibration), 4096);
+    }
+
+    #[test]
+    fn no_calibration_writes_while_calibrated() {
+        let mut ina = ina(Calibration::Calibration_32V_2A, 10_000, 5_000);
+        let before = ina.i2c.transactions();
+        ina.current_raw().unwrap();
+        ina.power_raw().unwrap();
+        assert_eq!(ina.i2c.transactions(), before + 2);
+    }
+
+    #[test]
+    fn idle_rail_is_checked_once() {
+        let mut ina = ina(Calibration::Calibration_32V_2A, 0, 5_000);
+        let before = ina.i2c.transactions();
+        assert_eq!(ina.current_raw().unwrap(), 0);
+        assert_eq!(ina.i2c.transactions(), before + 2);
+        ina.current_raw().unwrap();
+        ina.power_raw().unwrap();
+        assert_eq!(ina.i2c.transactions(), before + 4);
+
+        // load comes and goes, then the chip browns out
+        ina.i2c.set_shunt_voltage_uV(10_000);
+        ina.i2c.convert();
+        assert_eq!(ina.current_raw().unwrap(), 1000);
+        ina.i2c.power_cycle();
+        assert_eq!(ina.current_raw().unwrap(), 0);
+        assert_eq!(ina.reset_count(), 1);
+    }
+
+    #[test]
+    fn software_reset_restores_configuration() {
+        let mut ina = ina(Calibration::Calibration_16V_400mA, 25_000, 5_000);
+        ina.reset().unwrap();
+        assert_eq!(ina.i2c.register(Register::Configuration), 0x019F);
+        assert_eq!(ina.i2c.register(Register::Calibration), 8192);
+        assert_eq!(ina.reset_count(), 0);
+    }
+
+    #[test]
+    fn probe_reports_configuration() {
+        let mut ina = INA219::new(SimulatedINA219::new());
+        assert_eq!(ina.probe().unwrap(), Probe::PowerOnDefault);
+        ina.init(Calibration::Calibration_16V_400mA).unwrap();
+        assert_eq!(ina.probe().unwrap(), Probe::Configured);
+        ina.i2c.set_register(Register::Configuration, 0x0187);
+        assert_eq!(ina.probe().unwrap(), Probe::Unknown(0x0187));
+        assert_eq!(ina.verify(), Err(Error::UnexpectedConfiguration(0x0187)));
+    }
+
+    #[test]
+    fn verify_ignores_operating_mode() {
+        let mut ina = ina(Calibration::Calibration_32V_2A, 0, 5_000);
+        ina.power_down().unwrap();
+        assert_eq!(ina.verify(), Ok(Probe::Configured));
+        ina.trigger().unwrap();
+        assert_eq!(ina.verify(), Ok(Probe::Configured));
+    }
+
+    #[test]
+    fn verify_missing_chip() {
+        let mut ina = INA219::with_address(SimulatedINA219::new(), 0x45);
+        assert_eq!(ina.verify(), Err(Error::NotDetected(0x45)));
+
+        let mut ina = INA219::with_address(SimulatedINA219::with_address(0x45), 0x45);
+        ina.init(Calibration::Calibration_32V_1A).unwrap();
+        assert_eq!(ina.verify(), Ok(Probe::Configured));
+        assert_eq!(ina.address(), 0x45);
+    }
+
+    #[test]
+    fn verify_rejects_other_chips() {
+        let mut ina = INA219::new(SimulatedINA219::new());
+        ina.i2c.set_register(Register::Configuration, 0x4127);
+        assert_eq!(ina.verify(), Err(Error::NotIna219(INA219_ADDR)));
+    }
+
+    #[test]
+    fn scan_finds_chips() {
+        let mut sim = SimulatedINA219::with_address(0x44);
+        assert_eq!(scan(&mut sim).unwrap(), [0x44]);
+        sim.set_register(Register::Configuration, 0x4127);
+        assert!(scan(&mut sim).unwrap().is_empty());
+        // e.g. an EEPROM full of zeros
+        sim.set_register(Register::Configuration, 0);
+        assert!(scan(&mut sim).unwrap().is_empty());
+
+        // an adapter that does not report empty addresses as NACKs
+        let mut sim = SimulatedINA219::with_address(0x45);
+        sim.set_remote_io(true);
+        assert_eq!(scan(&mut sim).unwrap(), [0x45]);
+        // errors after the chip answered are not swallowed
+        let mut flaky = Flaky(SimulatedINA219::with_address(0x45));
+        assert_eq!(scan(&mut flaky), Err(SimError::RemoteIo));
+    }
+
+    // drops every transaction to the chip after the first
+    struct Flaky(SimulatedINA219);
+
+    impl embedded_hal::i2c::ErrorType for Flaky {
+        type Error = SimError;
+    }
+
+    impl I2c for Flaky {
+        fn transaction(&mut self, address: u8, operations: &mut [Operation<'_>]) -> Result<(), SimError> {
+            if address == self.0.address() && self.0.transactions() > 0 {
+                return Err(SimError::RemoteIo);
+            }
+            self.0.transaction(address, operations)
+        }
+    }
+
+    #[test]
+    fn attach_reads_existing_configuration() {
+        for cal in all::<Calibration>() {
+            let g = golden(cal);
+            let mut bootloader = ina(g.cal.clone(), g.shunt_uV, g.bus_mV);
+            let expected = bootloader.measure().unwrap();
+            let sim = bootloader.release();
+            let writes = sim.transactions();
+
+            let mut ina = INA219::attach(sim, INA219_ADDR, 100 * MilliOhm).unwrap();
+            assert_eq!(ina.config(), g.config);
+            assert_eq!(ina.calibration_value(), g.cal_value);
+            assert_eq!(ina.measure().unwrap(), expected);
+            assert_eq!(ina.power_lsb(), 20 * ina.current_lsb());
+            assert_eq!(ina.probe().unwrap(), Probe::Configured);
+            // two register reads, one snapshot and one probe; no writes
+            assert_eq!(ina.release().transactions(), writes + 4);
+        }
+    }
+
+    #[test]
+    fn attach_derives_lsb_from_shunt() {
+        let mut ina = INA219::new(SimulatedINA219::new());
+        ina.calibrate(2 * MilliOhm, 15 * Ampere).unwrap();
+        let (lsb, cal) = (ina.current_lsb(), ina.calibration_value());
+        let ina = INA219::attach(ina.release(), INA219_ADDR, 2 * MilliOhm).unwrap();
+        assert_eq!((ina.current_lsb(), ina.calibration_value()), (lsb, cal));
+        assert!(ina.max_current() >= 15 * Ampere);
+    }
+
+    #[test]
+    fn attach_then_set_gain() {
+        let bootloader = ina(Calibration::Calibration_32V_2A, 150_000, 12_000);
+        let mut ina = INA219::attach(bootloader.release(), INA219_ADDR, 100 * MilliOhm).unwrap();
+        // 320 mV across 0.1 Ohm
+        assert_eq!(ina.max_current(), 3200 * MilliAmpere);
+        ina.set_gain(Gain::Gain_8_320mV).unwrap();
+        assert_eq!(
+            ina.set_gain(Gain::Gain_4_160mV),
+            Err(Error::GainTooLow(Gain::Gain_4_160mV, 320 * MilliVolt))
+        );
+        assert_eq!(ina.getCurrent_mA().unwrap(), 1500.0);
+    }
+
+    #[test]
+    fn attach_to_unconfigured_chip() {
+        let mut sim = SimulatedINA219::new();
+        sim.set_bus_voltage_mV(5_000);
+        let mut ina = INA219::attach(sim, INA219_ADDR, 100 * MilliOhm).unwrap();
+        assert_eq!(ina.config(), INA219_CONFIG_POWER_ON_DEFAULT);
+        assert_eq!(ina.current_lsb(), 0);
+        let m = ina.measure().unwrap();
+        assert_eq!((m.bus_voltage, m.current), (5 * Volt, 0));
+        assert_eq!(ina.reset_count(), 0);
+
+        assert_eq!(
+            INA219::attach(SimulatedINA219::new(), 0x41, 100 * MilliOhm).err(),
+            Some(Error::NotDetected(0x41))
+        );
+        assert_eq!(
+            INA219::attach(SimulatedINA219::new(), INA219_ADDR, 0).err(),
+            Some(Error::InvalidCalibration)
+        );
+    }
+
+    #[test]
+    fn range_and_gain_are_independent() {
+        let mut high = ina(Calibration::Calibration_32V_2A, 150_000, 12_000);
+        high.set_bus_voltage_range(BusVoltageRange::Range_16V).unwrap();
+        assert_eq!(high.i2c.register(Register::Configuration), 0x199F);
+        assert_eq!((high.bus_voltage_range(), high.gain()), (BusVoltageRange::Range_16V, Gain::Gain_8_320mV));
+        assert_eq!(high.getCurrent_mA().unwrap(), 1500.0);
+
+        let mut low = ina(Calibration::Calibration_16V_400mA, 25_000, 5_000);
+        low.set_gain(Gain::Gain_8_320mV).unwrap();
+        assert_eq!(low.i2c.register(Register::Configuration), 0x199F);
+        assert_eq!(low.i2c.register(Register::Calibration), 8192);
+        assert_eq!(low.getCurrent_mA().unwrap(), 250.0);
+    }
+
+    #[test]
+    fn gain_must_hold_max_current() {
+        let mut ina = INA219::new(SimulatedINA219::new());
+        ina.calibrate(100 * MilliOhm, Ampere).unwrap();
+        assert_eq!(ina.gain(), Gain::Gain_4_160mV);
+        ina.set_gain(Gain::Gain_8_320mV).unwrap();
+        assert_eq!(ina.i2c.register(Register::Configuration) & INA219_CONFIG_GAIN_MASK, INA219_CONFIG_GAIN_8_320MV);
+        assert_eq!(
+            ina.set_gain(Gain::Gain_2_80mV),
+            Err(Error::GainTooLow(Gain::Gain_2_80mV, 100 * MilliVolt))
+        );
+        assert_eq!(ina.gain(), Gain::Gain_8_320mV);
+    }
+
+    #[test]
+    fn range_and_gain_wait_for_wake() {
+        let mut ina = ina(Calibration::Calibration_32V_2A, 150_000, 12_000);
+        ina.power_down().unwrap();
+        ina.set_bus_voltage_range(BusVoltageRange::Range_16V).unwrap();
+        assert_eq!(ina.i2c.register(Register::Configuration), 0x3998);
+        ina.wake().unwrap();
+        assert_eq!(ina.i2c.register(Register::Configuration), 0x199F);
+    }
+
+    #[test]
+    fn smallest_gain_for_shunt_voltage() {
+        assert_eq!(Gain::for_shunt_voltage(40 * MilliVolt), Some(Gain::Gain_1_40mV));
+        assert_eq!(Gain::for_shunt_voltage(-41 * MilliVolt), Some(Gain::Gain_2_80mV));
+        assert_eq!(Gain::for_shunt_voltage(320 * MilliVolt), Some(Gain::Gain_8_320mV));
+        assert_eq!(Gain::for_shunt_voltage(321 * MilliVolt), None);
+    }
+
+    #[test]
+    fn field_calibration_single_point() {
+        // the shunt is really 0.098 Ohm, so the meter sees 1530 mA
+        let mut ina = ina(Calibration::Calibration_32V_2A, 150_000, 12_000);
+        assert_eq!(ina.current().unwrap(), 1500 * MilliAmpere);
+        let point = ina.calibration_point(1530 * MilliAmpere).unwrap();
+        let record = ina.field_calibrate(&[point]).unwrap();
+        // trunc(4096 * 1530 / 1500) = 4177, bit 0 cleared
+        assert_eq!(record.calibration, 4176);
+        assert_eq!(record.current_offset, 0);
+        assert_eq!(ina.i2c.register(Register::Calibration), 4176);
+        assert_eq!(ina.current().unwrap(), 15292 * 100 * MicroAmpere);
+        assert_eq!(ina.reset_count(), 0);
+    }
+
+    #[test]
+    fn set_gain_keeps_field_calibration() {
+        let mut ina = ina(Calibration::Calibration_32V_2A, 150_000, 12_000);
+        let point = ina.calibration_point(1530 * MilliAmpere).unwrap();
+        ina.field_calibrate(&[point]).unwrap();
+        let before = ina.i2c.transactions();
+        ina.set_gain(Gain::Gain_8_320mV).unwrap();
+        // only the configuration register is written
+        assert_eq!(ina.i2c.transactions(), before + 1);
+        assert_eq!(ina.calibration_value(), 4176);
+        assert_eq!(ina.i2c.register(Register::Calibration), 4176);
+    }
+
+    #[test]
+    fn field_calibration_fits_gain_and_offset() {
+        // the meter reads 2% more and 5 mA less than the chip
+        let mut ina = ina(Calibration::Calibration_32V_2A, 50_000, 12_000);
+        let low = ina.calibration_point(505 * MilliAmpere).unwrap();
+        ina.i2c.set_shunt_voltage_uV(150_000);
+        let high = ina.calibration_point(1525 * MilliAmpere).unwrap();
+        let record = ina.field_calibrate(&[low, high]).unwrap();
+        assert_eq!(record.calibration, 4176);
+        // 1015 mA - 1000 mA * 4176 / 4096
+        assert_eq!(record.current_offset, -4_531_250);
+
+        let m = ina.measure().unwrap();
+        assert_eq!(m.current, 1_529_200_000 - 4_531_250);
+        assert_eq!(m.power, m.current * 12);
+        assert!((1524 * MilliAmpere..1526 * MilliAmpere).contains(&ina.current().unwrap()));
+        assert_eq!(ina.power().unwrap(), m.power);
+
+        assert_eq!(ina.field_calibrate(&[]), Err(Error::InvalidCalibration));
+        assert_eq!(ina.field_calibrate(&[CalibrationPoint { reference: 0, measured: 0, calibration: 4096 }]), Err(Error::InvalidCalibration));
+    }
+
+    #[test]
+    fn calibration_record_round_trips() {
+        let record = CalibrationRecord {
+            shunt: 98 * MilliOhm,
+            current_lsb: 100 * MicroAmpere,
+            calibration: 4176,
+            current_offset: -4_531_250,
+            shunt_offset: 0,
+            max_current: 2 * Ampere,
+            tempco: 50,
+            reference_temperature: 20 * Celsius,
+            shunt_tolerance: 5000,
+        };
+        let bytes = record.to_bytes();
+        assert_eq!(CalibrationRecord::from_bytes(&bytes), Some(record));
+        assert_eq!(CalibrationRecord::from_bytes(&bytes[1..]), None);
+
+        let mut ina = ina(Calibration::Calibration_32V_2A, 150_000, 12_000);
+        ina.apply_calibration_record(&record).unwrap();
+        assert_eq!(ina.calibration_record(), record);
+        assert_eq!(ina.i2c.register(Register::Calibration), 4176);
+        assert_eq!(ina.current().unwrap(), 1_529_200_000 - 4_531_250);
+
+        ina.init(Calibration::Calibration_32V_2A).unwrap();
+        assert_eq!(ina.current_offset(), 0);
+    }
+
+    struct CountingDelay(u32);
+
+    impl DelayNs for CountingDelay {
+        fn delay_ns(&mut self, ns: u32) {
+            self.0 += ns;
+        }
+    }
+
+    #[test]
+    fn tare_zeroes_shunt_current_and_power() {
+        let mut ina = ina(Calibration::Calibration_32V_2A, 300, 12_000);
+        assert_eq!(ina.current().unwrap(), 3 * MilliAmpere);
+        let mut delay = CountingDelay(0);
+        assert_eq!(ina.tare(8, &mut delay).unwrap(), 300 * MicroVolt);
+        // seven waits of one 12 bit conversion
+        assert_eq!(delay.0, 7 * 532_000);
+        assert_eq!(ina.shunt_voltage_raw().unwrap(), 0);
+        assert_eq!(ina.current_raw().unwrap(), 0);
+        assert_eq!(ina.power_raw().unwrap(), 0);
+        assert_eq!(ina.measure().unwrap().current, 0);
+
+        ina.i2c.set_shunt_voltage_uV(150_300);
+        assert_eq!(ina.shunt_voltage().unwrap(), 150 * MilliVolt);
+        assert_eq!(ina.current().unwrap(), 1500 * MilliAmpere);
+        assert_eq!(ina.power_raw().unwrap(), 9000);
+        assert_eq!(ina.power().unwrap(), 18 * Watt);
+        let m = ina.measure().unwrap();
+        assert_eq!((m.shunt_voltage, m.current, m.power), (150 * MilliVolt, 1500 * MilliAmpere, 18 * Watt));
+        assert_eq!(ina.calibration_record().shunt_offset, 300 * MicroVolt);
+    }
+
+    #[test]
+    fn shunt_offset_is_restored() {
+        let mut ina = ina(Calibration::Calibration_32V_2A, -150_000, 12_000);
+        ina.set_shunt_offset(-204 * MicroVolt);
+        assert_eq!(ina.shunt_offset(), -200 * MicroVolt);
+        assert_eq!(ina.shunt_voltage().unwrap(), -149_800 * MicroVolt);
+        assert_eq!(ina.current().unwrap(), -1498 * MilliAmpere);
+        assert_eq!(ina.power().unwrap(), 17_976 * MilliWatt);
+    }
+
+    #[test]
+    fn calibrate_for_any_shunt() {
+        let mut ina = INA219::new(SimulatedINA219::new());
+        ina.calibrate(100 * MilliOhm, 2 * Ampere).unwrap();
+        assert_eq!(ina.current_lsb(), 100 * MicroAmpere);
+        assert_eq!(ina.i2c.register(Register::Calibration), 4096);
+        assert_eq!(ina.i2c.register(Register::Configuration), 0x399F);
+
+        ina.calibrate(100 * MilliOhm, Ampere).unwrap();
+        assert_eq!(ina.current_lsb(), 50 * MicroAmpere);
+        assert_eq!(ina.i2c.register(Register::Calibration), 8192);
+        assert_eq!(ina.i2c.register(Register::Configuration), 0x319F);
+
+        ina.i2c.set_shunt_voltage_uV(12_345);
+        ina.i2c.set_bus_voltage_mV(12_000);
+        ina.calibrate(2 * MilliOhm, 10 * Ampere).unwrap();
+        assert_eq!(ina.current_lsb(), 500 * MicroAmpere);
+        assert_eq!(ina.calibration_value(), 40960);
+        assert_eq!(ina.i2c.register(Register::Configuration), 0x219F);
+        assert_eq!((ina.shunt(), ina.max_current()), (2 * MilliOhm, 10 * Ampere));
+        // 12.34 mV across 2 mOhm
+        assert_eq!(ina.current().unwrap(), 6170 * MilliAmpere);
+    }
+
+    #[test]
+    fn calibrate_keeps_adc_settings() {
+        let mut ina = ina(Calibration::Calibration_16V_400mA, 0, 0);
+        ina.set_averaging(Averaging::Samples16).unwrap();
+        ina.calibrate(100 * MilliOhm, 300 * MilliAmpere).unwrap();
+        // 16 V range, 16 sample averaging, 40 mV range for 30 mV
+        assert_eq!(ina.config(), 0x0667);
+    }
+
+    #[test]
+    fn calibrate_rejects_impossible_ranges() {
+        let mut ina = INA219::new(SimulatedINA219::new());
+        assert_eq!(ina.calibrate(Ohm, Ampere), Err(Error::ShuntVoltageOutOfRange(Volt)));
+        assert_eq!(ina.calibrate(0, Ampere), Err(Error::InvalidCalibration));
+        assert_eq!(ina.calibrate(100 * MilliOhm, -Ampere), Err(Error::InvalidCalibration));
+    }
+
+    #[test]
+    fn negative_current() {
+        let mut ina = ina(Calibration::Calibration_32V_1A, -50_000, 12_000);
+        assert_eq!(ina.getCurrent_mA().unwrap(), -500.0);
+        assert_eq!(ina.getShuntVoltage_mV().unwrap(), -50.0);
+    }
+
+    #[test]
+    fn discharge_has_negative_power() {
+        let mut ina = ina(Calibration::Calibration_32V_1A, -50_000, 12_000);
+        assert_eq!(ina.getPower_mW().unwrap(), 6000.0);
+        assert_eq!(ina.getSignedPower_mW().unwrap(), -6000.0);
+        assert_eq!(ina.direction(10 * MilliAmpere).unwrap(), Direction::Discharging);
+        assert_eq!(ina.direction(600 * MilliAmpere).unwrap(), Direction::Idle);
+        assert_eq!(ina.direction(10 * MilliAmpere).unwrap().reversed(), Direction::Charging);
+
+        let m = ina.measure().unwrap();
+        assert_eq!((m.power, m.signed_power()), (6 * Watt, -6 * Watt));
+        let pm = ina.sense().unwrap();
+        assert_eq!((pm.Power, pm.signed_power()), (6000.0, -6000.0));
+        assert_eq!(pm.direction(-10.0), Direction::Discharging);
+        assert_eq!(Direction::of(5 * MilliAmpere, 10 * MilliAmpere), Direction::Idle);
+        assert_eq!(Direction::of(11 * MilliAmpere, 10 * MilliAmpere), Direction::Charging);
+    }
+
+    #[test]
+    fn tempco_compensation() {
+        let g = golden(Calibration::Calibration_32V_2A);
+        let mut ina = ina(g.cal, g.shunt_uV, g.bus_mV);
+        assert_eq!(ina.current_at(75 * Celsius).unwrap(), 1500 * MilliAmpere);
+
+        // a copper trace shunt, +20 % at 75 °C
+        ina.set_tempco(4000, 25 * Celsius);
+        assert_eq!(ina.current_at(25 * Celsius).unwrap(), 1500 * MilliAmpere);
+        assert_eq!(ina.current_at(75 * Celsius).unwrap(), 1250 * MilliAmpere);
+        assert_eq!(ina.power_at(75 * Celsius).unwrap(), 15 * Watt);
+        let m = ina.measure_at(-25 * Celsius).unwrap();
+        assert_eq!((m.current, m.power, m.shunt_voltage), (1_875_000_000, 22_500_000_000, 150 * MilliVolt));
+        assert_eq!(ina.compensate(-1500 * MilliAmpere, 75 * Celsius), -1250 * MilliAmpere);
+        assert_eq!(ina.current().unwrap(), 1500 * MilliAmpere);
+    }
+
+    #[test]
+    fn bus_voltage_drops_status_bits() {
+        let mut ina = ina(Calibration::Calibration_32V_2A, 0, 3_301);
+        // 4 mV LSB, CNVR set in the register
+        assert_eq!(ina.voltage_raw().unwrap(), 3300);
+    }
+
+    #[test]
+    fn sense_collects_all_readings() {
+        let g = golden(Calibration::Calibration_32V_2A);
+        let mut ina = ina(g.cal, g.shunt_uV, g.bus_mV);
+        let pm = ina.sense().unwrap();
+        assert_eq!(pm.Voltage, 12.0);
+        assert_eq!(pm.Shunt, 150.0);
+        assert_eq!(pm.Current, 1500.0);
+        assert_eq!(pm.Power, 18000.0);
+    }
+
+    #[test]
+    fn sense_propagates_bus_errors() {
+        let mut ina = ina(Calibration::Calibration_32V_2A, 0, 0);
+        ina.i2c.nack_next(1);
+        assert!(ina.sense().is_err());
+    }
+
+    #[test]
+    fn averaging_and_power_down() {
+        let mut ina = ina(Calibration::Calibration_32V_2A, 0, 5_000);
+        ina.set_averaging(Averaging::Samples128).unwrap();
+        assert_eq!(ina.i2c.register(Register::Configuration), 0x3FFF);
+        ina.power_down().unwrap();
+        assert_eq!(ina.i2c.register(Register::Configuration), 0x3FF8);
+        ina.set_averaging(Averaging::Samples2).unwrap();
+        assert_eq!(ina.i2c.register(Register::Configuration), 0x3FF8);
+        ina.wake().unwrap();
+        assert_eq!(ina.i2c.register(Register::Configuration), 0x3CCF);
+    }
+
+    #[test]
+    fn power_down_before_calibration() {
+        let mut ina = INA219::new(SimulatedINA219::new());
+        ina.power_down().unwrap();
+        assert_eq!(ina.i2c.register(Register::Configuration), 0x3998);
+        ina.wake().unwrap();
+        assert_eq!(ina.i2c.register(Register::Configuration), 0x399F);
+    }
+
+    #[test]
+    fn averaging_before_calibration_keeps_converting() {
+        let mut ina = INA219::new(SimulatedINA219::new());
+        ina.set_averaging(Averaging::Samples4).unwrap();
+        assert_eq!(ina.i2c.register(Register::Configuration), 0x3D57);
+    }
+
+    proptest! {
+        #[test]
+        fn current_matches_silicon_math(shunt_uV in -320_000i32..=320_000, half_cal in 1u16..=0x7FFF) {
+            let mut ina = ina(Calibration::Calibration_32V_2A, shunt_uV, 12_000);
+            let cal = half_cal * 2;
+            let record = CalibrationRecord { calibration: cal, ..ina.calibration_record() };
+            ina.apply_calibration_record(&record).unwrap();
+            // Current = ShuntVoltage * Cal / 4096, saturating
+            let expected = (shunt_uV / 10 * cal as i32 / 4096).clamp(i16::MIN as i32, i16::MAX as i32);
+            let raw = ina.current_raw().unwrap();
+            prop_assert_eq!(raw as i32, expected);
+            prop_assert_eq!(ina.current().unwrap(), raw as i64 * 100 * MicroAmpere);
+            prop_assert!((ina.getCurrent_mA().unwrap() - raw as f32 / 10.0).abs() <= 1e-3);
+        }
+    }
 }
@@ -2,8 +2,31 @@
 
 extern crate alloc;
 extern crate embedded_hal;
+#[cfg(any(test, feature = "storage"))]
+extern crate embedded_storage;
 extern crate enum_iterator;
+#[cfg(any(test, feature = "std"))]
+extern crate std;
+#[cfg(test)]
+extern crate proptest;
 
+pub mod alert;
+pub mod battery;
+pub mod design;
+pub mod efficiency;
 pub mod error;
+#[cfg(any(test, feature = "std"))]
+pub mod hwmon;
 pub mod ina219;
-mod physic;
+#[cfg(any(test, feature = "std"))]
+pub mod logger;
+pub mod metrics;
+pub mod physic;
+pub mod rails;
+pub mod sensor;
+#[cfg(any(test, feature = "sim"))]
+pub mod sim;
+#[cfg(any(test, feature = "storage"))]
+pub mod storage;
+pub mod trace;
+pub mod uncertainty;
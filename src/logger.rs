@@ -0,0 +1,620 @@
+//! Long running captures of INA219 readings to CSV or JSON-lines files.
+//!
+//! Every log starts with a header describing the calibration, the shunt and
+//! the ADC settings the samples were taken with, followed by one timestamped
+//! line per sample:
+//!
+//! ```text
+//! # ina219 calibration=4096 config=14751 shunt_ohm=0.1 max_current_A=2 current_lsb_A=0.0001 power_lsb_W=0.002
+//! # adc range_V=32 pga_mV=320 bus_adc=12bit shunt_adc=12bit mode=continuous
+//! time_s,bus_voltage_V,shunt_voltage_V,current_A,power_W
+//! 1700000000.25,12,0.15,1.5,18
+//! ```
+//!
+//! [`Replay`] reads either format back into [`Record`]s.
+
+use std::format;
+use std::fs::File;
+use std::io::{self, BufRead, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::string::String;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use std::vec::Vec;
+
+use embedded_hal::i2c::I2c;
+
+use crate::ina219::{Measurement, PowerMonitor, INA219};
+use crate::physic::{decimalAsNano, nanoAsDecimal, ElectricCurrent, ElectricResistance, Power};
+
+const CSV_COLUMNS: &str = "time_s,bus_voltage_V,shunt_voltage_V,current_A,power_W";
+const JSON_HEADER_KEY: &str = "{\"ina219\":";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Csv,
+    JsonLines,
+}
+
+/// Settings of the device at the start of a capture.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Header {
+    pub calibration: u16,
+    pub config: u16,
+    pub shunt: ElectricResistance,
+    pub max_current: ElectricCurrent,
+    pub current_lsb: ElectricCurrent,
+    pub power_lsb: Power,
+}
+
+impl Header {
+    pub fn of<I2C: I2c>(ina: &INA219<I2C>) -> Header {
+        Header {
+            calibration: ina.calibration_value(),
+            config: ina.config(),
+            shunt: ina.shunt(),
+            max_current: ina.max_current(),
+            current_lsb: ina.current_lsb(),
+            power_lsb: ina.power_lsb(),
+        }
+    }
+
+    fn fields(&self) -> [(&'static str, String); 6] {
+        [
+            ("calibration", format!("{}", self.calibration)),
+            ("config", format!("{}", self.config)),
+            ("shunt_ohm", nanoAsDecimal(self.shunt)),
+            ("max_current_A", nanoAsDecimal(self.max_current)),
+            ("current_lsb_A", nanoAsDecimal(self.current_lsb)),
+            ("power_lsb_W", nanoAsDecimal(self.power_lsb)),
+        ]
+    }
+
+    // Decoded ADC settings; informational only, the config field is what
+    // gets parsed back. The first ADC_NUMBERS are plain numbers.
+    fn adc(&self) -> [(&'static str, String); 5] {
+        let c = self.config;
+        [
+            ("range_V", format!("{}", if c & 0x2000 != 0 { 32 } else { 16 })),
+            ("pga_mV", format!("{}", 40 << ((c >> 11) & 0x3))),
+            ("bus_adc", adc_resolution((c >> 7) & 0xF)),
+            ("shunt_adc", adc_resolution((c >> 3) & 0xF)),
+            ("mode", String::from(MODES[(c & 0x7) as usize])),
+        ]
+    }
+
+    fn set(&mut self, key: &str, value: &str) -> io::Result<()> {
+        match key {
+            "calibration" => self.calibration = value.parse().map_err(|_| invalid(value))?,
+            "config" => self.config = value.parse().map_err(|_| invalid(value))?,
+            "shunt_ohm" => self.shunt = nano(value)?,
+            "max_current_A" => self.max_current = nano(value)?,
+            "current_lsb_A" => self.current_lsb = nano(value)?,
+            "power_lsb_W" => self.power_lsb = nano(value)?,
+            _ => {}
+        }
+        Ok(())
+    }
+}
+
+const ADC_NUMBERS: usize = 2;
+
+const MODES: [&str; 8] = [
+    "power-down",
+    "shunt-triggered",
+    "bus-triggered",
+    "triggered",
+    "adc-off",
+    "shunt-continuous",
+    "bus-continuous",
+    "continuous",
+];
+
+fn adc_resolution(code: u16) -> String {
+    if code & 0x8 == 0 {
+        format!("{}bit", 9 + (code & 0x3))
+    } else {
+        format!("12bit/{}", 1 << (code & 0x7))
+    }
+}
+
+/// One sample, stamped with the time since the Unix epoch.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Record {
+    pub time: Duration,
+    pub measurement: Measurement,
+}
+
+impl Record {
+    pub fn monitor(&self) -> PowerMonitor {
+        self.measurement.into()
+    }
+
+    fn fields(&self) -> [(&'static str, String); 5] {
+        let m = &self.measurement;
+        [
+            ("time_s", nanoAsDecimal(self.time.as_nanos() as i64)),
+            ("bus_voltage_V", nanoAsDecimal(m.bus_voltage)),
+            ("shunt_voltage_V", nanoAsDecimal(m.shunt_voltage)),
+            ("current_A", nanoAsDecimal(m.current)),
+            ("power_W", nanoAsDecimal(m.power)),
+        ]
+    }
+
+    fn set(&mut self, key: &str, value: &str) -> io::Result<()> {
+        match key {
+            "time_s" => {
+                let t = nano(value)?;
+                if t < 0 {
+                    return Err(invalid(value));
+                }
+                self.time = Duration::from_nanos(t as u64);
+            }
+            "bus_voltage_V" => self.measurement.bus_voltage = nano(value)?,
+            "shunt_voltage_V" => self.measurement.shunt_voltage = nano(value)?,
+            "current_A" => self.measurement.current = nano(value)?,
+            "power_W" => self.measurement.power = nano(value)?,
+            _ => return Err(invalid(key)),
+        }
+        Ok(())
+    }
+}
+
+/// Destination of timestamped samples.
+pub trait Sink {
+    fn write_record(&mut self, record: &Record) -> io::Result<()>;
+}
+
+/// Writes the header on creation and one line per record.
+pub struct Logger<W: Write> {
+    out: W,
+    format: Format,
+    records: u64,
+    bytes: u64,
+}
+
+impl<W: Write> Logger<W> {
+    pub fn new(out: W, format: Format, header: &Header) -> io::Result<Logger<W>> {
+        let mut text = String::new();
+        match format {
+            Format::Csv => {
+                text.push_str("# ina219");
+                push_pairs(&mut text, &header.fields());
+                text.push_str("\n# adc");
+                push_pairs(&mut text, &header.adc());
+                text.push('\n');
+                text.push_str(CSV_COLUMNS);
+                text.push('\n');
+            }
+            Format::JsonLines => {
+                let adc = header.adc();
+                let (numbers, strings) = adc.split_at(ADC_NUMBERS);
+                text.push_str(JSON_HEADER_KEY);
+                push_json(&mut text, &[&header.fields()[..], numbers].concat(), strings);
+                text.push_str("}\n");
+            }
+        }
+        let mut logger = Logger {
+            out,
+            format,
+            records: 0,
+            bytes: 0,
+        };
+        logger.emit(&text)?;
+        Ok(logger)
+    }
+
+    pub fn format(&self) -> Format {
+        self.format
+    }
+
+    /// Number of records written so far.
+    pub fn records(&self) -> u64 {
+        self.records
+    }
+
+    /// Number of bytes written so far, header included.
+    pub fn bytes(&self) -> u64 {
+        self.bytes
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+
+    pub fn into_inner(self) -> W {
+        self.out
+    }
+
+    fn emit(&mut self, text: &str) -> io::Result<()> {
+        self.out.write_all(text.as_bytes())?;
+        self.bytes += text.len() as u64;
+        Ok(())
+    }
+}
+
+impl<W: Write> Sink for Logger<W> {
+    fn write_record(&mut self, record: &Record) -> io::Result<()> {
+        let mut text = String::new();
+        match self.format {
+            Format::Csv => {
+                for (i, (_, value)) in record.fields().iter().enumerate() {
+                    if i > 0 {
+                        text.push(',');
+                    }
+                    text.push_str(value);
+                }
+            }
+            Format::JsonLines => push_json(&mut text, &record.fields(), &[]),
+        }
+        text.push('\n');
+        self.emit(&text)?;
+        self.records += 1;
+        Ok(())
+    }
+}
+
+fn push_pairs(text: &mut String, pairs: &[(&str, String)]) {
+    for (key, value) in pairs {
+        text.push(' ');
+        text.push_str(key);
+        text.push('=');
+        text.push_str(value);
+    }
+}
+
+// Numbers are written bare, `strings` quoted.
+fn push_json(text: &mut String, numbers: &[(&str, String)], strings: &[(&str, String)]) {
+    text.push('{');
+    let quoted = strings.iter().map(|(k, v)| (k, format!("\"{}\"", v)));
+    for (i, (key, value)) in numbers.iter().map(|(k, v)| (k, v.clone())).chain(quoted).enumerate() {
+        if i > 0 {
+            text.push(',');
+        }
+        text.push('"');
+        text.push_str(key);
+        text.push_str("\":");
+        text.push_str(&value);
+    }
+    text.push('}');
+}
+
+/// When a [`RotatingLogger`] moves on to the next file; `None` means no limit.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Rotation {
+    pub max_bytes: Option<u64>,
+    pub max_records: Option<u64>,
+}
+
+/// Logs to `capture.csv`, `capture.1.csv`, `capture.2.csv`, ... starting a
+/// new file, with its own header, whenever the [`Rotation`] limit is hit.
+pub struct RotatingLogger {
+    path: PathBuf,
+    header: Header,
+    rotation: Rotation,
+    paths: Vec<PathBuf>,
+    logger: Logger<BufWriter<File>>,
+}
+
+impl RotatingLogger {
+    pub fn create<P: AsRef<Path>>(path: P, format: Format, header: &Header, rotation: Rotation) -> io::Result<RotatingLogger> {
+        let path = path.as_ref().to_path_buf();
+        let logger = Logger::new(BufWriter::new(File::create(&path)?), format, header)?;
+        Ok(RotatingLogger {
+            paths: std::vec![path.clone()],
+            path,
+            header: *header,
+            rotation,
+            logger,
+        })
+    }
+
+    /// Files written so far, oldest first.
+    pub fn paths(&self) -> &[PathBuf] {
+        &self.paths
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.logger.flush()
+    }
+
+    fn full(&self) -> bool {
+        let records = self.rotation.max_records.is_some_and(|max| self.logger.records() >= max);
+        let bytes = self.rotation.max_bytes.is_some_and(|max| self.logger.bytes() >= max);
+        self.logger.records() > 0 && (records || bytes)
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        self.logger.flush()?;
+        let mut name = self.path.file_stem().unwrap_or_default().to_os_string();
+        name.push(format!(".{}", self.paths.len()));
+        if let Some(ext) = self.path.extension() {
+            name.push(".");
+            name.push(ext);
+        }
+        let path = self.path.with_file_name(name);
+        self.logger = Logger::new(BufWriter::new(File::create(&path)?), self.logger.format(), &self.header)?;
+        self.paths.push(path);
+        Ok(())
+    }
+}
+
+impl Sink for RotatingLogger {
+    fn write_record(&mut self, record: &Record) -> io::Result<()> {
+        if self.full() {
+            self.rotate()?;
+        }
+        self.logger.write_record(record)
+    }
+}
+
+/// Samples `ina` every `interval`, `count` times or until an error, and
+/// writes the timestamped measurements to `sink`.
+pub fn run<I2C: I2c, S: Sink>(ina: &mut INA219<I2C>, sink: &mut S, interval: Duration, count: Option<u64>) -> io::Result<()> {
+    let mut next = Instant::now();
+    let mut n = 0u64;
+    while count.is_none_or(|count| n < count) {
+        let measurement = ina.measure().map_err(|e| io::Error::other(format!("{:?}", e)))?;
+        let time = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+        sink.write_record(&Record { time, measurement })?;
+        n += 1;
+        if count == Some(n) {
+            break;
+        }
+        // schedule against the previous deadline so that slow writes do not
+        // add up
+        next += interval;
+        let now = Instant::now();
+        if next > now {
+            thread::sleep(next - now);
+        }
+    }
+    Ok(())
+}
+
+/// Reads a log written by [`Logger`] back into records.
+pub struct Replay<R: BufRead> {
+    lines: io::Lines<R>,
+    format: Format,
+    header: Header,
+    pending: Option<String>,
+}
+
+impl<R: BufRead> Replay<R> {
+    /// Reads the header, detecting the format from the first line.
+    pub fn new(reader: R) -> io::Result<Replay<R>> {
+        let mut lines = reader.lines();
+        let mut header = Header::default();
+        let mut pending = None;
+        let mut format = None;
+        for line in lines.by_ref() {
+            let line = line?;
+            if let Some(comment) = line.strip_prefix('#') {
+                format = Some(Format::Csv);
+                for pair in comment.split_whitespace() {
+                    if let Some((key, value)) = pair.split_once('=') {
+                        header.set(key, value)?;
+                    }
+                }
+            } else if line == CSV_COLUMNS {
+                format = Some(Format::Csv);
+                break;
+            } else if let Some(object) = line.strip_prefix(JSON_HEADER_KEY) {
+                format = Some(Format::JsonLines);
+                for (key, value) in json_fields(object.strip_suffix('}').unwrap_or(object))? {
+                    header.set(key, value)?;
+                }
+                break;
+            } else {
+                pending = Some(line);
+                break;
+            }
+        }
+        let format = match (format, &pending) {
+            (Some(format), _) => format,
+            (None, Some(line)) if line.starts_with('{') => Format::JsonLines,
+            (None, None) => Format::Csv,
+            (None, Some(line)) => return Err(invalid(line)),
+        };
+        Ok(Replay {
+            lines,
+            format,
+            header,
+            pending,
+        })
+    }
+
+    pub fn format(&self) -> Format {
+        self.format
+    }
+
+    pub fn header(&self) -> &Header {
+        &self.header
+    }
+
+    fn parse(&self, line: &str) -> io::Result<Record> {
+        let mut record = Record::default();
+        match self.format {
+            Format::Csv => {
+                let keys = CSV_COLUMNS.split(',');
+                let values: Vec<&str> = line.split(',').collect();
+                if values.len() != keys.clone().count() {
+                    return Err(invalid(line));
+                }
+                for (key, value) in keys.zip(values) {
+                    record.set(key, value.trim())?;
+                }
+            }
+            Format::JsonLines => {
+                for (key, value) in json_fields(line)? {
+                    record.set(key, value)?;
+                }
+            }
+        }
+        Ok(record)
+    }
+}
+
+impl<R: BufRead> Iterator for Replay<R> {
+    type Item = io::Result<Record>;
+
+    fn next(&mut self) -> Option<io::Result<Record>> {
+        loop {
+            let line = match self.pending.take() {
+                Some(line) => line,
+                None => match self.lines.next()? {
+                    Ok(line) => line,
+                    Err(e) => return Some(Err(e)),
+                },
+            };
+            if line.trim().is_empty() {
+                continue;
+            }
+            return Some(self.parse(&line));
+        }
+    }
+}
+
+// Splits a flat JSON object of numbers and plain strings, as written by
+// Logger, into key/value pairs.
+fn json_fields(object: &str) -> io::Result<Vec<(&str, &str)>> {
+    let body = object
+        .trim()
+        .strip_prefix('{')
+        .and_then(|o| o.strip_suffix('}'))
+        .ok_or_else(|| invalid(object))?;
+    let mut fields = Vec::new();
+    for pair in body.split(',').filter(|p| !p.trim().is_empty()) {
+        let (key, value) = pair.split_once(':').ok_or_else(|| invalid(pair))?;
+        fields.push((key.trim().trim_matches('"'), value.trim().trim_matches('"')));
+    }
+    Ok(fields)
+}
+
+fn nano(value: &str) -> io::Result<i64> {
+    decimalAsNano(value).map_err(|_| invalid(value))
+}
+
+fn invalid(value: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("unexpected {:?} in log", value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ina219::Calibration;
+    use crate::sim::SimulatedINA219;
+    use std::fs;
+
+    fn header() -> Header {
+        let mut ina = INA219::new(SimulatedINA219::new());
+        ina.init(Calibration::Calibration_32V_2A).unwrap();
+        Header::of(&ina)
+    }
+
+    fn record(secs: u64, millis: u32) -> Record {
+        Record {
+            time: Duration::new(secs, millis * 1_000_000),
+            measurement: Measurement {
+                shunt_voltage: 150_000_000,
+                bus_voltage: 12_000_000_000,
+                current: 1_500_000_000,
+                power: -18_000_000_000,
+            },
+        }
+    }
+
+    #[test]
+    fn csv_layout() {
+        let mut logger = Logger::new(Vec::new(), Format::Csv, &header()).unwrap();
+        logger.write_record(&record(1_700_000_000, 250)).unwrap();
+        assert_eq!(
+            std::string::String::from_utf8(logger.into_inner()).unwrap(),
+            "# ina219 calibration=4096 config=14751 shunt_ohm=0.1 max_current_A=2 current_lsb_A=0.0001 power_lsb_W=0.002\n\
+             # adc range_V=32 pga_mV=320 bus_adc=12bit shunt_adc=12bit mode=continuous\n\
+             time_s,bus_voltage_V,shunt_voltage_V,current_A,power_W\n\
+             1700000000.25,12,0.15,1.5,-18\n"
+        );
+    }
+
+    #[test]
+    fn json_lines_layout() {
+        let mut logger = Logger::new(Vec::new(), Format::JsonLines, &header()).unwrap();
+        logger.write_record(&record(1_700_000_000, 250)).unwrap();
+        assert_eq!(
+            std::string::String::from_utf8(logger.into_inner()).unwrap(),
+            "{\"ina219\":{\"calibration\":4096,\"config\":14751,\"shunt_ohm\":0.1,\"max_current_A\":2,\
+             \"current_lsb_A\":0.0001,\"power_lsb_W\":0.002,\"range_V\":32,\"pga_mV\":320,\
+             \"bus_adc\":\"12bit\",\"shunt_adc\":\"12bit\",\"mode\":\"continuous\"}}\n\
+             {\"time_s\":1700000000.25,\"bus_voltage_V\":12,\"shunt_voltage_V\":0.15,\"current_A\":1.5,\"power_W\":-18}\n"
+        );
+    }
+
+    #[test]
+    fn replay_round_trips() {
+        for format in [Format::Csv, Format::JsonLines] {
+            let records = [record(1, 0), record(2, 1), record(3, 999)];
+            let mut logger = Logger::new(Vec::new(), format, &header()).unwrap();
+            for r in &records {
+                logger.write_record(r).unwrap();
+            }
+            let log = logger.into_inner();
+            let replay = Replay::new(&log[..]).unwrap();
+            assert_eq!(replay.format(), format);
+            assert_eq!(*replay.header(), header());
+            let replayed: Vec<Record> = replay.map(Result::unwrap).collect();
+            assert_eq!(replayed, records);
+        }
+    }
+
+    #[test]
+    fn replay_rejects_garbage() {
+        let log = "time_s,bus_voltage_V,shunt_voltage_V,current_A,power_W\n1,2,3\n";
+        let mut replay = Replay::new(log.as_bytes()).unwrap();
+        assert_eq!(replay.next().unwrap().unwrap_err().kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn rotates_files_with_headers() {
+        let dir = std::env::temp_dir().join(format!("ina219-logger-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let rotation = Rotation {
+            max_bytes: None,
+            max_records: Some(2),
+        };
+        let mut logger = RotatingLogger::create(dir.join("capture.csv"), Format::Csv, &header(), rotation).unwrap();
+        for i in 0..5 {
+            logger.write_record(&record(i, 0)).unwrap();
+        }
+        logger.flush().unwrap();
+        let names: Vec<_> = logger.paths().iter().map(|p| p.file_name().unwrap().to_str().unwrap()).collect();
+        assert_eq!(names, ["capture.csv", "capture.1.csv", "capture.2.csv"]);
+
+        let mut times = Vec::new();
+        for path in logger.paths() {
+            let replay = Replay::new(io::BufReader::new(File::open(path).unwrap())).unwrap();
+            assert_eq!(*replay.header(), header());
+            times.extend(replay.map(|r| r.unwrap().time.as_secs()));
+        }
+        assert_eq!(times, [0, 1, 2, 3, 4]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn samples_at_interval() {
+        let mut ina = INA219::new(SimulatedINA219::with_inputs(150_000, 12_000));
+        ina.init(Calibration::Calibration_32V_2A).unwrap();
+
+        let mut logger = Logger::new(Vec::new(), Format::Csv, &Header::of(&ina)).unwrap();
+        let start = Instant::now();
+        run(&mut ina, &mut logger, Duration::from_millis(5), Some(3)).unwrap();
+        assert!(start.elapsed() >= Duration::from_millis(10));
+        assert_eq!(logger.records(), 3);
+
+        let log = logger.into_inner();
+        let records: Vec<Record> = Replay::new(&log[..]).unwrap().map(Result::unwrap).collect();
+        assert!(records.windows(2).all(|w| w[0].time <= w[1].time));
+        let monitor = records[2].monitor();
+        assert_eq!((monitor.Voltage, monitor.Current), (12.0, 1500.0));
+    }
+}
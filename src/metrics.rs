@@ -0,0 +1,216 @@
+//! OpenMetrics text exposition of power readings, as scraped by Prometheus.
+//!
+//! ```text
+//! # TYPE ina219_current_amperes gauge
+//! # UNIT ina219_current_amperes amperes
+//! # HELP ina219_current_amperes Current through the shunt resistor.
+//! ina219_current_amperes{bus="/dev/i2c-1",address="0x40",rail="5V"} 1.5
+//! ...
+//! # EOF
+//! ```
+
+use core::fmt::Write;
+
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use crate::ina219::Measurement;
+use crate::physic::nanoAsDecimal;
+use crate::sensor::PowerSensor;
+
+/// Labels that tell the devices of one exporter apart.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Labels {
+    pub bus: String,
+    pub address: u8,
+    pub rail: String,
+}
+
+impl Labels {
+    pub fn new(bus: &str, address: u8, rail: &str) -> Labels {
+        Labels {
+            bus: String::from(bus),
+            address,
+            rail: String::from(rail),
+        }
+    }
+}
+
+struct Family {
+    name: &'static str,
+    unit: &'static str,
+    help: &'static str,
+    value: fn(&Measurement) -> i64,
+}
+
+const FAMILIES: [Family; 4] = [
+    Family {
+        name: "ina219_bus_voltage_volts",
+        unit: "volts",
+        help: "Voltage between IN- and ground.",
+        value: bus_voltage,
+    },
+    Family {
+        name: "ina219_shunt_voltage_volts",
+        unit: "volts",
+        help: "Voltage across the shunt resistor.",
+        value: shunt_voltage,
+    },
+    Family {
+        name: "ina219_current_amperes",
+        unit: "amperes",
+        help: "Current through the shunt resistor.",
+        value: current,
+    },
+    Family {
+        name: "ina219_power_watts",
+        unit: "watts",
+        help: "Power delivered to the load.",
+        value: power,
+    },
+];
+
+fn bus_voltage(m: &Measurement) -> i64 {
+    m.bus_voltage
+}
+
+fn shunt_voltage(m: &Measurement) -> i64 {
+    m.shunt_voltage
+}
+
+fn current(m: &Measurement) -> i64 {
+    m.current
+}
+
+fn power(m: &Measurement) -> i64 {
+    m.power
+}
+
+/// Collects readings of several devices and renders them as one OpenMetrics
+/// exposition. Devices that failed to read are reported with `ina219_up 0`.
+#[derive(Default)]
+pub struct Exporter {
+    readings: Vec<(Labels, Option<Measurement>)>,
+}
+
+impl Exporter {
+    pub fn new() -> Exporter {
+        Exporter { readings: Vec::new() }
+    }
+
+    pub fn add(&mut self, labels: Labels, measurement: Measurement) {
+        self.readings.push((labels, Some(measurement)));
+    }
+
+    pub fn add_failure(&mut self, labels: Labels) {
+        self.readings.push((labels, None));
+    }
+
+    /// Takes a reading from `sensor`, recording a failure instead of
+    /// returning the error so that the other devices are still exported.
+    pub fn scrape<S: PowerSensor>(&mut self, labels: Labels, sensor: &mut S) {
+        match sensor.measure() {
+            Ok(m) => self.add(labels, m),
+            Err(_) => self.add_failure(labels),
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.readings.clear();
+    }
+
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        // writing to a String cannot fail
+        let _ = self.write_to(&mut out);
+        out
+    }
+
+    pub fn write_to<W: Write>(&self, out: &mut W) -> core::fmt::Result {
+        writeln!(out, "# TYPE ina219_up gauge")?;
+        writeln!(out, "# HELP ina219_up Whether the last reading of the device succeeded.")?;
+        for (labels, m) in &self.readings {
+            write_sample(out, "ina219_up", labels, if m.is_some() { "1" } else { "0" })?;
+        }
+        for family in FAMILIES.iter() {
+            writeln!(out, "# TYPE {} gauge", family.name)?;
+            writeln!(out, "# UNIT {} {}", family.name, family.unit)?;
+            writeln!(out, "# HELP {} {}", family.name, family.help)?;
+            for (labels, m) in &self.readings {
+                if let Some(m) = m {
+                    write_sample(out, family.name, labels, &nanoAsDecimal((family.value)(m)))?;
+                }
+            }
+        }
+        writeln!(out, "# EOF")
+    }
+}
+
+fn write_sample<W: Write>(out: &mut W, name: &str, labels: &Labels, value: &str) -> core::fmt::Result {
+    write!(out, "{}{{bus=\"", name)?;
+    write_escaped(out, &labels.bus)?;
+    write!(out, "\",address=\"{:#04x}\",rail=\"", labels.address)?;
+    write_escaped(out, &labels.rail)?;
+    writeln!(out, "\"}} {}", value)
+}
+
+fn write_escaped<W: Write>(out: &mut W, value: &str) -> core::fmt::Result {
+    for c in value.chars() {
+        match c {
+            '\\' => out.write_str("\\\\")?,
+            '"' => out.write_str("\\\"")?,
+            '\n' => out.write_str("\\n")?,
+            c => out.write_char(c)?,
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ina219::{Calibration, INA219};
+    use crate::sim::SimulatedINA219;
+
+    #[test]
+    fn renders_simulated_devices() {
+        let mut ina = INA219::new(SimulatedINA219::with_inputs(150_000, 5_000));
+        ina.init(Calibration::Calibration_32V_2A).unwrap();
+
+        let mut missing = INA219::with_address(SimulatedINA219::new(), 0x41);
+
+        let mut exporter = Exporter::new();
+        exporter.scrape(Labels::new("/dev/i2c-1", 0x40, "5V"), &mut ina);
+        exporter.scrape(Labels::new("/dev/i2c-1", 0x41, "3V3 \"core\""), &mut missing);
+        assert_eq!(
+            exporter.render(),
+            "# TYPE ina219_up gauge\n\
+             # HELP ina219_up Whether the last reading of the device succeeded.\n\
+             ina219_up{bus=\"/dev/i2c-1\",address=\"0x40\",rail=\"5V\"} 1\n\
+             ina219_up{bus=\"/dev/i2c-1\",address=\"0x41\",rail=\"3V3 \\\"core\\\"\"} 0\n\
+             # TYPE ina219_bus_voltage_volts gauge\n\
+             # UNIT ina219_bus_voltage_volts volts\n\
+             # HELP ina219_bus_voltage_volts Voltage between IN- and ground.\n\
+             ina219_bus_voltage_volts{bus=\"/dev/i2c-1\",address=\"0x40\",rail=\"5V\"} 5\n\
+             # TYPE ina219_shunt_voltage_volts gauge\n\
+             # UNIT ina219_shunt_voltage_volts volts\n\
+             # HELP ina219_shunt_voltage_volts Voltage across the shunt resistor.\n\
+             ina219_shunt_voltage_volts{bus=\"/dev/i2c-1\",address=\"0x40\",rail=\"5V\"} 0.15\n\
+             # TYPE ina219_current_amperes gauge\n\
+             # UNIT ina219_current_amperes amperes\n\
+             # HELP ina219_current_amperes Current through the shunt resistor.\n\
+             ina219_current_amperes{bus=\"/dev/i2c-1\",address=\"0x40\",rail=\"5V\"} 1.5\n\
+             # TYPE ina219_power_watts gauge\n\
+             # UNIT ina219_power_watts watts\n\
+             # HELP ina219_power_watts Power delivered to the load.\n\
+             ina219_power_watts{bus=\"/dev/i2c-1\",address=\"0x40\",rail=\"5V\"} 7.5\n\
+             # EOF\n"
+        );
+    }
+
+    #[test]
+    fn empty_exposition_is_terminated() {
+        let exporter = Exporter::new();
+        assert!(exporter.render().ends_with("# EOF\n"));
+    }
+}
@@ -60,6 +60,28 @@ pub const GigaWatt: Power = 1000 * MegaWatt;
 pub const maxPower: Power = 9223372036854775807 * NanoWatt;
 pub const minPower: Power = -9223372036854775807 * NanoWatt;
 
+//ElectricCharge is a measurement of electric charge stored as an int64 nano Coulomb.
+pub type ElectricCharge = i64;
+pub const NanoCoulomb: ElectricCharge = 1;
+pub const MicroCoulomb: ElectricCharge = 1000 * NanoCoulomb;
+pub const MilliCoulomb: ElectricCharge = 1000 * MicroCoulomb;
+pub const Coulomb: ElectricCharge = 1000 * MilliCoulomb;
+pub const KiloCoulomb: ElectricCharge = 1000 * Coulomb;
+// ampere hours, as batteries are rated
+pub const MilliAmpereHour: ElectricCharge = 3600 * MilliCoulomb;
+pub const AmpereHour: ElectricCharge = 3600 * Coulomb;
+pub const maxElectricCharge: ElectricCharge = 9223372036854775807 * NanoCoulomb;
+pub const minElectricCharge: ElectricCharge = -9223372036854775807 * NanoCoulomb;
+
+//Temperature is a measurement of temperature stored as an int64 nano degree Celsius.
+pub type Temperature = i64;
+pub const NanoCelsius: Temperature = 1;
+pub const MicroCelsius: Temperature = 1000 * NanoCelsius;
+pub const MilliCelsius: Temperature = 1000 * MicroCelsius;
+pub const Celsius: Temperature = 1000 * MilliCelsius;
+pub const maxTemperature: Temperature = 9223372036854775807 * NanoCelsius;
+pub const minTemperature: Temperature = -9223372036854775807 * NanoCelsius;
+
 
 pub trait ToStringPhysic_potential {
     fn to_string_physic_potential(self) -> String;
@@ -67,7 +89,7 @@ pub trait ToStringPhysic_potential {
 
 impl ToStringPhysic_potential for ElectricPotential {
     fn to_string_physic_potential(self) -> String {
-        return nanoAsString(self) + "V";
+        nanoAsString(self) + "V"
     }
 }
 
@@ -77,7 +99,7 @@ pub trait ToStringPhysic_power {
 
 impl ToStringPhysic_power for Power {
     fn to_string_physic_power(self) -> String {
-        return nanoAsString(self) + "W";
+        nanoAsString(self) + "W"
     }
 }
 
@@ -87,7 +109,7 @@ pub trait ToStringPhysic_current {
 
 impl ToStringPhysic_current for ElectricCurrent {
     fn to_string_physic_current(self) -> String {
-        return nanoAsString(self) + "A";
+        nanoAsString(self) + "A"
     }
 }
 
@@ -97,7 +119,27 @@ pub trait ToStringPhysic_resistance {
 
 impl ToStringPhysic_resistance for ElectricResistance {
     fn to_string_physic_resistance(self) -> String {
-        return nanoAsString(self) + "Ω";
+        nanoAsString(self) + "Ω"
+    }
+}
+
+pub trait ToStringPhysic_charge {
+    fn to_string_physic_charge(self) -> String;
+}
+
+impl ToStringPhysic_charge for ElectricCharge {
+    fn to_string_physic_charge(self) -> String {
+        nanoAsString(self) + "C"
+    }
+}
+
+pub trait ToStringPhysic_temperature {
+    fn to_string_physic_temperature(self) -> String;
+}
+
+impl ToStringPhysic_temperature for Temperature {
+    fn to_string_physic_temperature(self) -> String {
+        nanoAsString(self) + "°C"
     }
 }
 
@@ -105,7 +147,7 @@ fn nanoAsString(mut v: i64) -> String {
     let mut sign: String = String::from("");
     if v < 0 {
         if v == -9223372036854775808 {
-            v = v + 1;
+            v += 1;
         }
         sign = String::from("-");
         v = -v;
@@ -120,50 +162,50 @@ fn nanoAsString(mut v: i64) -> String {
             precision = v % 1000000000000000;
             base = (v / 1000000000000000) as i32;
             if precision > 500000000000000 {
-                base = base + 1;
+                base += 1;
             }
             frac = base % 1000;
-            base = base / 1000;
+            base /= 1000;
             unit = String::from("G");
         }
         Some(v) if v >= 999999500000001 => {
             precision = v % 1000000000000;
             base = (v / 1000000000000) as i32;
             if precision > 500000000000 {
-                base = base + 1;
+                base += 1;
             }
             frac = base % 1000;
-            base = base / 1000;
+            base /= 1000;
             unit = String::from("M");
         }
         Some(v) if v >= 999999500001 => {
             precision = v % 1000000000;
             base = (v / 1000000000) as i32;
             if precision > 500000000 {
-                base = base + 1;
+                base += 1;
             }
             frac = base % 1000;
-            base = base / 1000;
+            base /= 1000;
             unit = String::from("k");
         }
         Some(v) if v >= 999999501 => {
             precision = v % 1000000;
             base = (v / 1000000) as i32;
             if precision > 500000 {
-                base = base + 1;
+                base += 1;
             }
             frac = base % 1000;
-            base = base / 1000;
+            base /= 1000;
             unit = String::from("");
         }
         Some(v) if v >= 1000000 => {
             precision = v % 1000;
             base = (v / 1000) as i32;
             if precision > 500 {
-                base = base + 1;
+                base += 1;
             }
             frac = base % 1000;
-            base = base / 1000;
+            base /= 1000;
             unit = String::from("m");
         }
         Some(v) if v >= 1000 => {
@@ -175,7 +217,7 @@ fn nanoAsString(mut v: i64) -> String {
             base = v as i32;
             unit = String::from("n");
         }
-        Some(v) if v == 0 => {
+        Some(0) => {
             return String::from("0");
         }
         None => {}
@@ -185,7 +227,21 @@ fn nanoAsString(mut v: i64) -> String {
     if frac == 0 {
         return sign + &base.to_string() + &unit;
     }
-    return sign + &base.to_string() + &String::from(".") + &prefixZeros(3, frac) + &unit;
+    sign + &base.to_string() + &String::from(".") + &prefixZeros(3, frac) + &unit
+}
+
+// Formats nano units as a plain decimal number of base units, without an SI
+// prefix and without rounding, e.g. 1500000 -> "0.0015".
+pub fn nanoAsDecimal(v: i64) -> String {
+    let sign = if v < 0 { "-" } else { "" };
+    let v = v.unsigned_abs();
+    let base = (v / 1_000_000_000).to_string();
+    let frac = prefixZeros(9, (v % 1_000_000_000) as i32);
+    let frac = frac.trim_end_matches('0');
+    if frac.is_empty() {
+        return String::from(sign) + &base;
+    }
+    String::from(sign) + &base + "." + frac
 }
 
 fn prefixZeros(digits: i32, v: i32) -> String {
@@ -195,7 +251,7 @@ fn prefixZeros(digits: i32, v: i32) -> String {
         s = String::from("0") + &s;
         str_len += 1;
     }
-    return s;
+    s
 }
 
 pub const maxInt64: i64 = 9223372036854775807;
@@ -207,15 +263,6 @@ pub struct decimal {
     neg: bool,
 }
 
-//Reverse the string
-fn reverse(input: &str) -> String {
-    let mut output = String::new();
-    for c in input.chars().rev() {
-        output.push(c)
-    }
-    output
-}
-
 // Converts a string to a decimal form. The return int is how many bytes of the
 // string are considered numeric. The string may contain +-0 prefixes and
 // arbitrary suffixes as trailing non number characters are ignored.
@@ -239,7 +286,7 @@ fn atod(s: &str) -> (decimal, usize, PhysicError) {
     for (i, &item) in bytes.iter().enumerate() {
         let val = Option::Some(item);
         match val {
-            Some(val) if val == b'-' => {
+            Some(b'-') => {
                 if seenDigit {
                     end = i;
                     break;
@@ -253,7 +300,7 @@ fn atod(s: &str) -> (decimal, usize, PhysicError) {
                 d.neg = true;
                 start += 1;
             }
-            Some(val) if val == b'+' => {
+            Some(b'+') => {
                 if seenDigit {
                     end = i;
                     break;
@@ -267,7 +314,7 @@ fn atod(s: &str) -> (decimal, usize, PhysicError) {
                 seenPlus = true;
                 start += 1;
             }
-            Some(val) if val == b'.' => {
+            Some(b'.') => {
                 if isPoint {
                     return (d, 0, PhysicError::MultipleDecimalPoints);
                 }
@@ -277,13 +324,13 @@ fn atod(s: &str) -> (decimal, usize, PhysicError) {
                     start += 1;
                 }
             }
-            Some(val) if val == b'0' => {
+            Some(b'0') => {
                 if !seenDigit {
                     start += 1;
                 }
                 seenZero = true;
             }
-            Some(val) if val >= b'1' && val <= b'9' => {
+            Some(val) if (b'1'..=b'9').contains(&val) => {
                 seenDigit = true;
             }
             _ => {
@@ -299,25 +346,20 @@ fn atod(s: &str) -> (decimal, usize, PhysicError) {
     let mut exp: i32 = 0;
 
     // Strip non significant zeros to find base exponent.
-    let bytes_part2 = &s[start..end];
-
-    //revert the string
-    let bytes_part2_t = reverse(bytes_part2);
-
-    let bytes_part2_temp = bytes_part2_t.as_bytes();
-
-    for (i, &item) in bytes_part2_temp.iter().enumerate() {
-        let val = Option::Some(item);
+    let mut i = end;
+    while i > start {
+        i -= 1;
+        let val = Option::Some(bytes[i]);
         match val {
-            Some(val) if val >= b'1' && val <= b'9' => {
+            Some(val) if (b'1'..=b'9').contains(&val) => {
                 seenDigit = true;
             }
-            Some(val) if val == b'.' => {
+            Some(b'.') => {
                 if !seenDigit {
                     end -= 1;
                 }
             }
-            Some(val) if val == b'0' => {
+            Some(b'0') => {
                 if !seenDigit {
                     if i > dp {
                         end -= 1;
@@ -335,15 +377,15 @@ fn atod(s: &str) -> (decimal, usize, PhysicError) {
     }
 
     let bytes_part3 = &bytes[start..end];
-    for (_, &item) in bytes_part3.iter().enumerate() {
-        if item >= b'0' && item <= b'9' {
+    for &item in bytes_part3.iter() {
+        if item.is_ascii_digit() {
             // *10 is decimal shift left.
             d.base *= 10;
             //Convert ascii digit into number
             let check = d.base + ((item - b'0') as u64);
             // Check should always be larger than u unless we have overflowed.
             // Similarly if check > max it will overflow when converted to int64.
-            if check < d.base || (check as i64) > maxInt64 {
+            if check < d.base || check > maxInt64 as u64 {
                 if d.neg {
                     return (d, 0, PhysicError::ErrOverFlowsInt64Negative);
                 }
@@ -364,14 +406,14 @@ fn atod(s: &str) -> (decimal, usize, PhysicError) {
         }
         // Find the exponent based on decimal point distance from left and the
         // length of the number.
-        d.exp = ((dp - start) - (end - start)) as i32;
+        d.exp = (dp as i32 - start as i32) - (end as i32 - start as i32);
         if dp <= start {
             // Account for numbers of the form 1 > n < -1 eg 0.0001.
             d.exp += 1;
         }
     }
 
-    return (d, last, PhysicError::Null);
+    (d, last, PhysicError::Null)
 }
 
 pub type prefix = i32;
@@ -388,44 +430,409 @@ pub const Mega: prefix = 6;
 pub const Giga: prefix = 9;
 pub const Tera: prefix = 12;
 
-fn parseSIPrefix(r: u8) -> (prefix, usize) {
-    let r_copy = r.clone();
-    let val = Option::Some(r_copy);
+fn parseSIPrefix(r: char) -> (prefix, usize) {
+    let val = Option::Some(r);
     match val {
-        Some(val) if val == b'p' => {
-            return (Pico, "p".len());
+        Some('p') => {
+            (Pico, "p".len())
         }
-        Some(val) if val == b'n' => {
-            return (Nano, "n".len());
+        Some('n') => {
+            (Nano, "n".len())
         }
-        Some(val) if val == b'u' => {
-            return (Micro, "u".len());
+        Some('u') => {
+            (Micro, "u".len())
         }
-        // \xB5 => µ
-        Some(val) if val == b'\xB5' => {
-            return (Micro, "µ".len());
+        Some('µ') => {
+            (Micro, "µ".len())
         }
-        Some(val) if val == b'm' => {
-            return (Milli, "m".len());
+        Some('m') => {
+            (Milli, "m".len())
         }
-        Some(val) if val == b'k' => {
-            return (Kilo, "k".len());
+        Some('k') => {
+            (Kilo, "k".len())
         }
-        Some(val) if val == b'M' => {
-            return (Mega, "M".len());
+        Some('M') => {
+            (Mega, "M".len())
         }
-        Some(val) if val == b'G' => {
-            return (Giga, "G".len());
+        Some('G') => {
+            (Giga, "G".len())
         }
-        Some(val) if val == b'T' => {
-            return (Tera, "T".len());
+        Some('T') => {
+            (Tera, "T".len())
         }
         None => {
-            return (Unit, 0);
+            (Unit, 0)
         }
         _ => {
-            return (Unit, 0);
+            (Unit, 0)
         }
     }
 }
 
+// Converts a decimal to int64, scaled by 10^scale. Digits below the scale are
+// truncated.
+fn dtoi(d: decimal, scale: i32) -> Result<i64, PhysicError> {
+    let overflow = if d.neg {
+        PhysicError::ErrOverFlowsInt64Negative
+    } else {
+        PhysicError::ErrOverFlowsInt64
+    };
+    let mag = d.exp + scale;
+    if mag > 18 && d.base != 0 {
+        return Err(overflow);
+    }
+    let mut u = d.base;
+    if mag > 0 {
+        for _ in 0..mag {
+            u = match u.checked_mul(10) {
+                Some(u) => u,
+                None => return Err(overflow),
+            };
+        }
+    } else {
+        for _ in 0..(-mag).min(20) {
+            u /= 10;
+        }
+    }
+    if u > maxInt64 as u64 {
+        return Err(overflow);
+    }
+    if d.neg {
+        return Ok(-(u as i64));
+    }
+    Ok(u as i64)
+}
+
+// Parses a number with an optional SI prefix, e.g. "1.5k", into a value
+// scaled to the base prefix. The returned index is where the unit starts.
+fn valueOfUnitString(s: &str, base: prefix) -> Result<(i64, usize), PhysicError> {
+    let (d, mut n, err) = atod(s);
+    if err != PhysicError::Null {
+        return Err(err);
+    }
+    let mut si = Unit;
+    if let Some(r) = s[n..].chars().next() {
+        let (p, size) = parseSIPrefix(r);
+        si = p;
+        n += size;
+    }
+    let v = dtoi(d, si - base)?;
+    Ok((v, n))
+}
+
+// Parses a plain decimal number of base units into nano units, the inverse
+// of nanoAsDecimal. Digits below one nano unit are truncated.
+pub fn decimalAsNano(s: &str) -> Result<i64, PhysicError> {
+    let (d, n, err) = atod(s);
+    if err != PhysicError::Null {
+        return Err(err);
+    }
+    if n != s.len() {
+        return Err(PhysicError::UnexpectedEndOfString(String::from(&s[n..])));
+    }
+    dtoi(d, -Nano)
+}
+
+const validPrefixes: &str = "p,n,u,µ,m,k,M,G or T";
+
+// Parses a quantity stored as nano units, accepting any of the unit spellings.
+fn parsePhysic(s: &str, units: &[&str], max: i64, min: i64) -> Result<i64, PhysicError> {
+    let unit = String::from(units[0]);
+    let (v, n) = match valueOfUnitString(s, Nano) {
+        Ok(r) => r,
+        Err(PhysicError::ErrNotANumber) => return Err(PhysicError::NotNumberUnitErr(unit)),
+        Err(PhysicError::ErrOverFlowsInt64) => return Err(PhysicError::MaxValueErr(nanoAsString(max) + &unit)),
+        Err(PhysicError::ErrOverFlowsInt64Negative) => {
+            return Err(PhysicError::MinValueErr(nanoAsString(min) + &unit))
+        }
+        Err(e) => return Err(e),
+    };
+    let rest = &s[n..];
+    if units.contains(&rest) {
+        return Ok(v);
+    }
+    // "5m" is read as five milli-nothing
+    if rest.is_empty() {
+        return Err(PhysicError::NotUnitErr(unit));
+    }
+    for u in units {
+        if rest.len() > u.len() && rest.ends_with(u) {
+            let found = String::from(&rest[..rest.len() - u.len()]);
+            return Err(PhysicError::UnknownUnitPrefixErr(found, String::from(validPrefixes)));
+        }
+    }
+    Err(PhysicError::IncorrectUnitErr(unit))
+}
+
+pub trait PhysicElectricCurrentSet {
+    fn setCurrent(s: &str) -> Result<ElectricCurrent, PhysicError>;
+}
+
+impl PhysicElectricCurrentSet for ElectricCurrent {
+    fn setCurrent(s: &str) -> Result<ElectricCurrent, PhysicError> {
+        parsePhysic(s, &["A", "a"], maxElectricCurrent, minElectricCurrent)
+    }
+}
+
+pub trait PhysicElectricPotentialSet {
+    fn setVoltage(s: &str) -> Result<ElectricPotential, PhysicError>;
+}
+
+impl PhysicElectricPotentialSet for ElectricPotential {
+    fn setVoltage(s: &str) -> Result<ElectricPotential, PhysicError> {
+        parsePhysic(s, &["V", "v"], maxElectricPotential, minElectricPotential)
+    }
+}
+
+pub trait PhysicElectricResistanceSet {
+    fn setResistance(s: &str) -> Result<ElectricResistance, PhysicError>;
+}
+
+impl PhysicElectricResistanceSet for ElectricResistance {
+    fn setResistance(s: &str) -> Result<ElectricResistance, PhysicError> {
+        parsePhysic(s, &["Ω", "Ohm", "ohm"], maxElectricResistance, minElectricResistance)
+    }
+}
+
+pub trait PhysicPowerSet {
+    fn setPower(s: &str) -> Result<Power, PhysicError>;
+}
+
+impl PhysicPowerSet for Power {
+    fn setPower(s: &str) -> Result<Power, PhysicError> {
+        parsePhysic(s, &["W", "w"], maxPower, minPower)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    // value of a decimal, scaled by 10^scale, as an exact integer
+    fn scaled(d: decimal, scale: i32) -> i128 {
+        let mut v = d.base as i128;
+        let exp = d.exp + scale;
+        assert!(exp >= 0, "{} digits below the requested scale", -exp);
+        for _ in 0..exp {
+            v *= 10;
+        }
+        if d.neg {
+            -v
+        } else {
+            v
+        }
+    }
+
+    fn parse(s: &str) -> (decimal, usize) {
+        let (d, n, err) = atod(s);
+        assert_eq!(err, PhysicError::Null, "atod({:?})", s);
+        (d, n)
+    }
+
+    #[test]
+    fn atod_integers() {
+        let (d, n) = parse("123");
+        assert_eq!((scaled(d, 0), n), (123, 3));
+        let (d, n) = parse("1000");
+        assert_eq!((d.base, d.exp, n), (1, 3, 4));
+        let (d, n) = parse("+007");
+        assert_eq!((scaled(d, 0), n), (7, 4));
+        let (d, n) = parse("-10mA");
+        assert_eq!((scaled(d, 0), n), (-10, 3));
+    }
+
+    #[test]
+    fn atod_decimals() {
+        for &(s, value, n) in &[
+            ("1.5", 15_000, 3),
+            ("1.50", 15_000, 4),
+            ("0.5", 5_000, 3),
+            (".5", 5_000, 2),
+            ("00.5", 5_000, 4),
+            ("5.", 50_000, 2),
+            ("10.0", 100_000, 4),
+            ("0.0001", 1, 6),
+            ("-2.25", -22_500, 5),
+            ("1.2kV", 12_000, 3),
+        ] {
+            let (d, len) = parse(s);
+            assert_eq!((scaled(d, 4), len), (value, n), "atod({:?})", s);
+        }
+    }
+
+    #[test]
+    fn atod_errors() {
+        assert_eq!(atod("+-1").2, PhysicError::BothPlusAndMinussymbols);
+        assert_eq!(atod("-+1").2, PhysicError::BothPlusAndMinussymbols);
+        assert_eq!(atod("--1").2, PhysicError::MultipleMinusSymbols);
+        assert_eq!(atod("++1").2, PhysicError::MultiplePlusSymbols);
+        assert_eq!(atod("1.2.3").2, PhysicError::MultipleDecimalPoints);
+        assert_eq!(atod("mA").2, PhysicError::ErrNotANumber);
+        assert_eq!(atod("9223372036854775808").2, PhysicError::ErrOverFlowsInt64);
+        assert_eq!(atod("-9223372036854775808").2, PhysicError::ErrOverFlowsInt64Negative);
+        assert_eq!(atod("9223372036854775807").2, PhysicError::Null);
+    }
+
+    #[test]
+    fn si_prefixes() {
+        assert_eq!(parseSIPrefix('p'), (Pico, 1));
+        assert_eq!(parseSIPrefix('n'), (Nano, 1));
+        assert_eq!(parseSIPrefix('u'), (Micro, 1));
+        assert_eq!(parseSIPrefix('µ'), (Micro, 2));
+        assert_eq!(parseSIPrefix('m'), (Milli, 1));
+        assert_eq!(parseSIPrefix('k'), (Kilo, 1));
+        assert_eq!(parseSIPrefix('M'), (Mega, 1));
+        assert_eq!(parseSIPrefix('G'), (Giga, 1));
+        assert_eq!(parseSIPrefix('T'), (Tera, 1));
+        assert_eq!(parseSIPrefix('A'), (Unit, 0));
+    }
+
+    #[test]
+    fn nano_as_string() {
+        for &(v, s) in &[
+            (0, "0"),
+            (1, "1n"),
+            (999, "999n"),
+            (1000, "1µ"),
+            (1001, "1.001µ"),
+            (1500, "1.500µ"),
+            (10 * MilliAmpere, "10m"),
+            (1_234_567, "1.235m"),
+            (999_999_500, "999.999m"),
+            (999_999_501, "1"),
+            (Ampere, "1"),
+            (-Ampere, "-1"),
+            (2 * KiloAmpere + 5 * Ampere, "2.005k"),
+            (3 * MegaAmpere, "3M"),
+            (maxElectricCurrent, "9.223G"),
+            (i64::MIN, "-9.223G"),
+        ] {
+            assert_eq!(nanoAsString(v), s, "nanoAsString({})", v);
+        }
+    }
+
+    #[test]
+    fn nano_as_decimal() {
+        assert_eq!(nanoAsDecimal(0), "0");
+        assert_eq!(nanoAsDecimal(12 * Volt), "12");
+        assert_eq!(nanoAsDecimal(1500 * MicroAmpere), "0.0015");
+        assert_eq!(nanoAsDecimal(-1), "-0.000000001");
+        assert_eq!(nanoAsDecimal(i64::MIN), "-9223372036.854775808");
+    }
+
+    #[test]
+    fn decimal_as_nano() {
+        assert_eq!(decimalAsNano("12"), Ok(12 * Volt));
+        assert_eq!(decimalAsNano("0.0015"), Ok(1500 * MicroAmpere));
+        assert_eq!(decimalAsNano("-0.000000001"), Ok(-1));
+        assert_eq!(decimalAsNano("1.5V"), Err(PhysicError::UnexpectedEndOfString(String::from("V"))));
+        assert_eq!(decimalAsNano("x"), Err(PhysicError::ErrNotANumber));
+    }
+
+    #[test]
+    fn unit_suffixes() {
+        assert_eq!((15 * MilliAmpere).to_string_physic_current(), "15mA");
+        assert_eq!((-5 * Celsius).to_string_physic_temperature(), "-5°C");
+        assert_eq!((2 * AmpereHour).to_string_physic_charge(), "7.200kC");
+        assert_eq!((100 * MilliVolt).to_string_physic_potential(), "100mV");
+        assert_eq!((250 * Watt).to_string_physic_power(), "250W");
+        assert_eq!((100 * MilliOhm).to_string_physic_resistance(), "100mΩ");
+    }
+
+    // reads back a string produced by nanoAsString, in nano units
+    fn reparse(s: &str) -> f64 {
+        let (d, n) = parse(s);
+        let (si, _) = s[n..].chars().next().map(parseSIPrefix).unwrap_or((Unit, 0));
+        let v = d.base as f64 * 10f64.powi(d.exp + si - Nano);
+        if d.neg {
+            -v
+        } else {
+            v
+        }
+    }
+
+    #[test]
+    fn set_quantities() {
+        assert_eq!(ElectricCurrent::setCurrent("+15mA"), Ok(15 * MilliAmpere));
+        assert_eq!(ElectricCurrent::setCurrent("2A"), Ok(2 * Ampere));
+        assert_eq!(ElectricCurrent::setCurrent("-1.5µA"), Ok(-1500 * NanoAmpere));
+        assert_eq!(ElectricCurrent::setCurrent("1.5uA"), Ok(1500 * NanoAmpere));
+        assert_eq!(ElectricPotential::setVoltage("100mV"), Ok(100 * MilliVolt));
+        assert_eq!(ElectricPotential::setVoltage("0.5V"), Ok(500 * MilliVolt));
+        assert_eq!(Power::setPower("250W"), Ok(250 * Watt));
+        assert_eq!(Power::setPower("1.2kW"), Ok(1200 * Watt));
+        assert_eq!(ElectricResistance::setResistance("100mΩ"), Ok(100 * MilliOhm));
+        assert_eq!(ElectricResistance::setResistance("0.1Ohm"), Ok(100 * MilliOhm));
+        assert_eq!(ElectricResistance::setResistance("2mohm"), Ok(2 * MilliOhm));
+        // below one nano unit is truncated
+        assert_eq!(ElectricCurrent::setCurrent("0.1nA"), Ok(0));
+    }
+
+    #[test]
+    fn set_quantity_errors() {
+        let unit = || String::from("A");
+        assert_eq!(ElectricCurrent::setCurrent("15"), Err(PhysicError::NotUnitErr(unit())));
+        assert_eq!(ElectricCurrent::setCurrent("15m"), Err(PhysicError::NotUnitErr(unit())));
+        assert_eq!(ElectricCurrent::setCurrent("15V"), Err(PhysicError::IncorrectUnitErr(unit())));
+        assert_eq!(ElectricCurrent::setCurrent("mA"), Err(PhysicError::NotNumberUnitErr(unit())));
+        assert_eq!(
+            ElectricCurrent::setCurrent("15xA"),
+            Err(PhysicError::UnknownUnitPrefixErr(String::from("x"), String::from(validPrefixes)))
+        );
+        assert_eq!(
+            ElectricCurrent::setCurrent("10GA"),
+            Err(PhysicError::MaxValueErr(String::from("9.223GA")))
+        );
+        assert_eq!(
+            ElectricCurrent::setCurrent("-10GA"),
+            Err(PhysicError::MinValueErr(String::from("-9.223GA")))
+        );
+        assert_eq!(ElectricCurrent::setCurrent("1..5A"), Err(PhysicError::MultipleDecimalPoints));
+    }
+
+    proptest! {
+        #[test]
+        fn decimal_round_trips(v in (i64::MIN + 1)..=i64::MAX) {
+            prop_assert_eq!(decimalAsNano(&nanoAsDecimal(v)), Ok(v));
+        }
+
+        #[test]
+        fn set_current_round_trips(v in -1_000_000_000_000i64..1_000_000_000_000) {
+            let s = alloc::format!("{}nA", v);
+            prop_assert_eq!(ElectricCurrent::setCurrent(&s), Ok(v));
+        }
+
+        #[test]
+        fn atod_parses_any_integer(v in (i64::MIN + 1)..=i64::MAX) {
+            let s = v.to_string();
+            let (d, n) = parse(&s);
+            prop_assert_eq!(scaled(d, 0), v as i128);
+            prop_assert_eq!(n, s.len());
+        }
+
+        #[test]
+        fn atod_parses_any_decimal(int in 0u32.., frac in 0u32..1_000_000, neg: bool) {
+            let s = alloc::format!("{}{}.{:06}", if neg { "-" } else { "" }, int, frac);
+            let (d, _) = parse(&s);
+            let expected = int as i128 * 1_000_000 + frac as i128;
+            prop_assert_eq!(scaled(d, 6), if neg { -expected } else { expected });
+        }
+
+        #[test]
+        fn atod_stops_at_the_unit(v in 1u32.., unit in "[pnumkMGT]?[AVW]") {
+            let s = alloc::format!("{}{}", v, unit);
+            let (d, n) = parse(&s);
+            prop_assert_eq!(scaled(d, 0), v as i128);
+            prop_assert_eq!(&s[n..], unit.as_str());
+        }
+
+        #[test]
+        fn nano_as_string_round_trips(v in (i64::MIN + 1)..=i64::MAX) {
+            let s = nanoAsString(v);
+            let parsed = reparse(&s);
+            let error = (parsed - v as f64).abs();
+            prop_assert!(error <= 5e-4 * (v as f64).abs(), "{} -> {} -> {}", v, s, parsed);
+        }
+    }
+}
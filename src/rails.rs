@@ -0,0 +1,285 @@
+//! Power tree of a board with one INA219 per rail.
+//!
+//! Rails are registered with their parent, the rail they are supplied
+//! from, and sampled one per call in a round-robin schedule:
+//!
+//! ```ignore
+//! let mut rails = Registry::new();
+//! let input = rails.add("12V", 12 * Volt, None, ina_12v);
+//! rails.add("5V", 5 * Volt, Some(input), ina_5v);
+//! rails.add("3V3", 3300 * MilliVolt, Some(input), ina_3v3);
+//! loop {
+//!     rails.poll_next();
+//!     // what the converters between 12V and its children lose
+//!     let lost = rails.unaccounted(input);
+//! }
+//! ```
+//!
+//! Power is the power register reading, so every rail counts as a load.
+
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use embedded_hal::i2c::I2c;
+
+use crate::ina219::{Measurement, INA219};
+use crate::metrics::{Exporter, Labels};
+use crate::physic::{ElectricPotential, Power};
+
+/// Handle returned by [`Registry::add`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct RailId(usize);
+
+pub struct Rail<I2C> {
+    name: String,
+    nominal_voltage: ElectricPotential,
+    parent: Option<RailId>,
+    ina: INA219<I2C>,
+    last: Option<Measurement>,
+}
+
+impl<I2C> Rail<I2C> {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn nominal_voltage(&self) -> ElectricPotential {
+        self.nominal_voltage
+    }
+
+    pub fn parent(&self) -> Option<RailId> {
+        self.parent
+    }
+
+    /// The last reading, `None` before the first one and after a failed one.
+    pub fn last(&self) -> Option<&Measurement> {
+        self.last.as_ref()
+    }
+}
+
+/// One rail of a [`Registry::snapshot`], with the rails it supplies.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RailSnapshot {
+    pub name: String,
+    pub nominal_voltage: ElectricPotential,
+    pub measurement: Option<Measurement>,
+    /// Power of the rail not drawn by its children, see
+    /// [`Registry::unaccounted`].
+    pub unaccounted: Option<Power>,
+    pub children: Vec<RailSnapshot>,
+}
+
+/// Named rails, sampled round-robin.
+pub struct Registry<I2C> {
+    rails: Vec<Rail<I2C>>,
+    next: usize,
+}
+
+impl<I2C> Default for Registry<I2C> {
+    fn default() -> Self {
+        Registry::new()
+    }
+}
+
+impl<I2C> Registry<I2C> {
+    pub fn new() -> Registry<I2C> {
+        Registry {
+            rails: Vec::new(),
+            next: 0,
+        }
+    }
+
+    /// Registers a rail measured by a calibrated `ina`. A parent has to be
+    /// added before its children, which keeps the tree free of cycles.
+    pub fn add(&mut self, name: &str, nominal_voltage: ElectricPotential, parent: Option<RailId>, ina: INA219<I2C>) -> RailId {
+        if let Some(RailId(parent)) = parent {
+            assert!(parent < self.rails.len(), "unknown parent rail");
+        }
+        self.rails.push(Rail {
+            name: String::from(name),
+            nominal_voltage,
+            parent,
+            ina,
+            last: None,
+        });
+        RailId(self.rails.len() - 1)
+    }
+
+    pub fn find(&self, name: &str) -> Option<RailId> {
+        self.rails.iter().position(|rail| rail.name == name).map(RailId)
+    }
+
+    pub fn rail(&self, id: RailId) -> &Rail<I2C> {
+        &self.rails[id.0]
+    }
+
+    pub fn ina(&mut self, id: RailId) -> &mut INA219<I2C> {
+        &mut self.rails[id.0].ina
+    }
+
+    pub fn len(&self) -> usize {
+        self.rails.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rails.is_empty()
+    }
+
+    /// Rails supplied by `id`, in registration order.
+    pub fn children(&self, id: RailId) -> impl Iterator<Item = RailId> + '_ {
+        self.ids().filter(move |&child| self.rails[child.0].parent == Some(id))
+    }
+
+    /// Rails without a parent, the inputs of the board.
+    pub fn roots(&self) -> impl Iterator<Item = RailId> + '_ {
+        self.ids().filter(move |&id| self.rails[id.0].parent.is_none())
+    }
+
+    fn ids(&self) -> impl Iterator<Item = RailId> {
+        (0..self.rails.len()).map(RailId)
+    }
+
+    pub fn power(&self, id: RailId) -> Option<Power> {
+        self.rails[id.0].last.map(|m| m.power)
+    }
+
+    /// Power drawn from the board's inputs, i.e. of all root rails; `None`
+    /// until every root has a reading.
+    pub fn total_power(&self) -> Option<Power> {
+        self.roots().map(|id| self.power(id)).sum()
+    }
+
+    /// Power of `id` minus that of its children: converter losses, or loads
+    /// without a monitor of their own. `None` while a reading is missing
+    /// and for rails without children.
+    pub fn unaccounted(&self, id: RailId) -> Option<Power> {
+        let mut children = self.children(id).peekable();
+        children.peek()?;
+        let drawn: Option<Power> = children.map(|child| self.power(child)).sum();
+        Some(self.power(id)? - drawn?)
+    }
+
+    /// The tree of last readings, one entry per root rail.
+    pub fn snapshot(&self) -> Vec<RailSnapshot> {
+        self.roots().map(|id| self.snapshot_of(id)).collect()
+    }
+
+    fn snapshot_of(&self, id: RailId) -> RailSnapshot {
+        let rail = &self.rails[id.0];
+        RailSnapshot {
+            name: rail.name.clone(),
+            nominal_voltage: rail.nominal_voltage,
+            measurement: rail.last,
+            unaccounted: self.unaccounted(id),
+            children: self.children(id).map(|child| self.snapshot_of(child)).collect(),
+        }
+    }
+}
+
+impl<I2C: I2c> Registry<I2C> {
+    /// Samples the next rail in turn. A failed reading clears the rail's
+    /// last reading and is returned; the next call moves on regardless.
+    pub fn poll_next(&mut self) -> Option<(RailId, Result<Measurement, I2C::Error>)> {
+        if self.rails.is_empty() {
+            return None;
+        }
+        let id = RailId(self.next % self.rails.len());
+        self.next = (id.0 + 1) % self.rails.len();
+        let rail = &mut self.rails[id.0];
+        let result = rail.ina.measure();
+        rail.last = result.as_ref().ok().copied();
+        Some((id, result))
+    }
+
+    /// Samples every rail once, returning the number of failed readings.
+    pub fn poll_all(&mut self) -> usize {
+        (0..self.rails.len()).filter(|_| matches!(self.poll_next(), Some((_, Err(_))))).count()
+    }
+
+    /// Adds the last reading of every rail to `exporter`, labelled with the
+    /// rail name; rails without one are reported down.
+    pub fn export(&self, exporter: &mut Exporter, bus: &str) {
+        for rail in &self.rails {
+            let labels = Labels::new(bus, rail.ina.address(), &rail.name);
+            match rail.last {
+                Some(m) => exporter.add(labels, m),
+                None => exporter.add_failure(labels),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ina219::Calibration;
+    use crate::physic::{MilliVolt, MilliWatt, Volt, Watt};
+    use crate::sim::SimulatedINA219;
+
+    // 32V_1A: 10 µV shunt LSB is 100 µA through 0.1 Ω
+    fn ina(address: u8, milliamps: i32, bus_millivolts: u32) -> INA219<SimulatedINA219> {
+        let mut sim = SimulatedINA219::with_address(address);
+        sim.set_shunt_voltage_uV(milliamps * 100);
+        sim.set_bus_voltage_mV(bus_millivolts);
+        let mut ina = INA219::with_address(sim, address);
+        ina.init(Calibration::Calibration_32V_1A).unwrap();
+        ina
+    }
+
+    fn board() -> (Registry<SimulatedINA219>, RailId, RailId, RailId) {
+        let mut rails = Registry::new();
+        let input = rails.add("12V", 12 * Volt, None, ina(0x40, 500, 12_000));
+        let five = rails.add("5V", 5 * Volt, Some(input), ina(0x41, 800, 5_000));
+        let core = rails.add("3V3", 3300 * MilliVolt, Some(input), ina(0x44, 400, 3_300));
+        (rails, input, five, core)
+    }
+
+    #[test]
+    fn round_robin() {
+        let (mut rails, input, five, core) = board();
+        let order: Vec<_> = (0..4).map(|_| rails.poll_next().unwrap().0).collect();
+        assert_eq!(order, [input, five, core, input]);
+        assert_eq!(rails.find("3V3"), Some(core));
+        assert_eq!(rails.children(input).collect::<Vec<_>>(), [five, core]);
+        assert_eq!(Registry::<SimulatedINA219>::new().poll_next().map(|(id, _)| id), None);
+    }
+
+    #[test]
+    fn totals_and_unaccounted_power() {
+        let (mut rails, input, five, core) = board();
+        rails.poll_next();
+        assert_eq!(rails.total_power(), Some(6 * Watt));
+        assert_eq!(rails.unaccounted(input), None);
+        assert_eq!(rails.poll_all(), 0);
+        // 6 W in, 4 W + 1.32 W out
+        assert_eq!(rails.power(five), Some(4 * Watt));
+        assert_eq!(rails.power(core), Some(1320 * MilliWatt));
+        assert_eq!(rails.unaccounted(input), Some(680 * MilliWatt));
+        assert_eq!(rails.unaccounted(five), None);
+
+        let tree = rails.snapshot();
+        assert_eq!(tree.len(), 1);
+        assert_eq!(tree[0].name, "12V");
+        assert_eq!(tree[0].unaccounted, Some(680 * MilliWatt));
+        let children: Vec<_> = tree[0].children.iter().map(|c| (c.name.as_str(), c.measurement.unwrap().power)).collect();
+        assert_eq!(children, [("5V", 4 * Watt), ("3V3", 1320 * MilliWatt)]);
+    }
+
+    #[test]
+    fn failed_rails() {
+        let (mut rails, input, five, core) = board();
+        // nothing answers at 0x45
+        let missing = rails.add("1V8", 1800 * MilliVolt, Some(core), INA219::with_address(SimulatedINA219::new(), 0x45));
+        assert_eq!(rails.poll_all(), 1);
+        assert!(rails.rail(missing).last().is_none());
+        assert_eq!(rails.unaccounted(core), None);
+        assert_eq!(rails.unaccounted(input), Some(680 * MilliWatt));
+
+        let mut exporter = Exporter::new();
+        rails.export(&mut exporter, "/dev/i2c-1");
+        let text = exporter.render();
+        assert!(text.contains("ina219_up{bus=\"/dev/i2c-1\",address=\"0x45\",rail=\"1V8\"} 0\n"));
+        assert!(text.contains("ina219_power_watts{bus=\"/dev/i2c-1\",address=\"0x41\",rail=\"5V\"} 4\n"));
+        assert_eq!(rails.rail(five).nominal_voltage(), 5 * Volt);
+    }
+}
@@ -0,0 +1,47 @@
+#![allow(non_snake_case)]
+
+use enum_iterator::Sequence;
+
+use crate::ina219::{Measurement, PowerMonitor};
+
+/// Number of ADC samples averaged together into one conversion result.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Sequence)]
+pub enum Averaging {
+    Samples1,
+    Samples2,
+    Samples4,
+    Samples8,
+    Samples16,
+    Samples32,
+    Samples64,
+    Samples128,
+}
+
+/// Chip-agnostic interface to a current/power monitor.
+///
+/// Readings use the same units as [`PowerMonitor`], so telemetry code written
+/// against this trait does not care which part sits on the board.
+pub trait PowerSensor {
+    type Error;
+
+    fn bus_voltage_V(&mut self) -> Result<f32, Self::Error>;
+
+    fn shunt_voltage_mV(&mut self) -> Result<f32, Self::Error>;
+
+    fn current_mA(&mut self) -> Result<f32, Self::Error>;
+
+    fn power_mW(&mut self) -> Result<f32, Self::Error>;
+
+    /// Reads shunt voltage, bus voltage, current and power in one go.
+    fn sense(&mut self) -> Result<PowerMonitor, Self::Error>;
+
+    /// Same as `sense`, in integer nano-units.
+    fn measure(&mut self) -> Result<Measurement, Self::Error>;
+
+    fn set_averaging(&mut self, averaging: Averaging) -> Result<(), Self::Error>;
+
+    /// Stops conversions until `wake` is called.
+    fn power_down(&mut self) -> Result<(), Self::Error>;
+
+    fn wake(&mut self) -> Result<(), Self::Error>;
+}
@@ -0,0 +1,439 @@
+#![allow(non_snake_case)]
+
+//! Simulated INA219 for host-side tests.
+//!
+//! [`SimulatedINA219`] implements `embedded_hal::i2c::I2c` and behaves like the
+//! silicon: it keeps the register pointer between transactions, applies the
+//! PGA, BRNG, ADC resolution and operating mode from the configuration
+//! register, and derives the current and power registers from the programmed
+//! shunt and bus voltage with the calibration register, setting CNVR and OVF
+//! the same way the chip does.
+
+use embedded_hal::i2c::{Error, ErrorKind, ErrorType, I2c, NoAcknowledgeSource, Operation};
+
+use crate::ina219::{Register, INA219_ADDR};
+
+/// Configuration register value after power-on or reset.
+pub const CONFIG_POWER_ON_DEFAULT: u16 = 0x399F;
+
+const CONFIG_RESET: u16 = 0x8000;
+const CONFIG_BRNG: u16 = 0x2000;
+const CONFIG_MODE_MASK: u16 = 0x0007;
+
+const BUS_CNVR: u16 = 0x0002;
+const BUS_OVF: u16 = 0x0001;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SimError {
+    Nack(NoAcknowledgeSource),
+    /// A NACK as some adapters report it, e.g. EREMOTEIO on i2c-bcm2835.
+    RemoteIo,
+}
+
+impl Error for SimError {
+    fn kind(&self) -> ErrorKind {
+        match *self {
+            SimError::Nack(source) => ErrorKind::NoAcknowledge(source),
+            SimError::RemoteIo => ErrorKind::Other,
+        }
+    }
+}
+
+pub struct SimulatedINA219 {
+    address: u8,
+    pointer: u8,
+    config: u16,
+    shunt: i16,
+    bus: u16,
+    power: u16,
+    current: i16,
+    calibration: u16,
+    shunt_uV: i32,
+    bus_mV: u32,
+    present: bool,
+    nacks: usize,
+    remote_io: bool,
+    transactions: usize,
+}
+
+impl Default for SimulatedINA219 {
+    fn default() -> Self {
+        SimulatedINA219::new()
+    }
+}
+
+impl SimulatedINA219 {
+    /// A chip at the default address, in its power-on state.
+    pub fn new() -> SimulatedINA219 {
+        SimulatedINA219::with_address(INA219_ADDR)
+    }
+
+    pub fn with_address(address: u8) -> SimulatedINA219 {
+        let mut sim = SimulatedINA219 {
+            address,
+            pointer: Register::Configuration,
+            config: CONFIG_POWER_ON_DEFAULT,
+            shunt: 0,
+            bus: 0,
+            power: 0,
+            current: 0,
+            calibration: 0,
+            shunt_uV: 0,
+            bus_mV: 0,
+            present: true,
+            nacks: 0,
+            remote_io: false,
+            transactions: 0,
+        };
+        sim.convert();
+        sim
+    }
+
+    /// A chip at the default address with `shunt_uV` across the shunt and
+    /// `bus_mV` on IN-, the starting point of most tests.
+    #[cfg(test)]
+    pub(crate) fn with_inputs(shunt_uV: i32, bus_mV: u32) -> SimulatedINA219 {
+        let mut sim = SimulatedINA219::new();
+        sim.set_shunt_voltage_uV(shunt_uV);
+        sim.set_bus_voltage_mV(bus_mV);
+        sim
+    }
+
+    pub fn address(&self) -> u8 {
+        self.address
+    }
+
+    /// Sets the differential voltage across the shunt, in microvolts.
+    pub fn set_shunt_voltage_uV(&mut self, microvolts: i32) {
+        self.shunt_uV = microvolts;
+        self.input_changed();
+    }
+
+    /// Sets the voltage on IN- with respect to ground, in millivolts.
+    pub fn set_bus_voltage_mV(&mut self, millivolts: u32) {
+        self.bus_mV = millivolts;
+        self.input_changed();
+    }
+
+    /// Reads a register without going through the bus.
+    pub fn register(&self, register: u8) -> u16 {
+        match register {
+            Register::Configuration => self.config,
+            Register::ShuntVoltage => self.shunt as u16,
+            Register::BusVoltage => self.bus,
+            Register::Power => self.power,
+            Register::Current => self.current as u16,
+            Register::Calibration => self.calibration,
+            _ => 0,
+        }
+    }
+
+    /// Writes a register without going through the bus, as another bus
+    /// master would.
+    pub fn set_register(&mut self, register: u8, value: u16) {
+        self.write_register(register, value);
+    }
+
+    /// Returns every register to its power-on value, like a brown-out does.
+    pub fn power_cycle(&mut self) {
+        self.config = CONFIG_POWER_ON_DEFAULT;
+        self.calibration = 0;
+        self.shunt = 0;
+        self.bus = 0;
+        self.power = 0;
+        self.current = 0;
+        self.pointer = Register::Configuration;
+        self.convert();
+    }
+
+    /// Runs one conversion with the current inputs, as the end of a
+    /// triggered or continuous conversion cycle would.
+    pub fn convert(&mut self) {
+        let mode = self.config & CONFIG_MODE_MASK;
+        // power-down (0) and ADC off (4) leave all registers untouched
+        if mode & 0x3 == 0 {
+            return;
+        }
+        if mode & 0x1 != 0 {
+            self.shunt = self.shunt_register();
+        }
+        if mode & 0x2 != 0 {
+            self.bus = self.bus_register();
+        }
+
+        let mut overflow = false;
+        let current = self.shunt as i32 * self.calibration as i32 / 4096;
+        if current > i16::MAX as i32 || current < i16::MIN as i32 {
+            overflow = true;
+            self.current = if current > 0 { i16::MAX } else { i16::MIN };
+        } else {
+            self.current = current as i16;
+        }
+
+        let power = (self.current as i32).abs() * (self.bus >> 3) as i32 / 5000;
+        if power > u16::MAX as i32 {
+            overflow = true;
+            self.power = u16::MAX;
+        } else {
+            self.power = power as u16;
+        }
+
+        self.bus |= BUS_CNVR;
+        if overflow {
+            self.bus |= BUS_OVF;
+        } else {
+            self.bus &= !BUS_OVF;
+        }
+    }
+
+    /// Makes the chip NACK its address for the next `count` transactions.
+    pub fn nack_next(&mut self, count: usize) {
+        self.nacks = count;
+    }
+
+    /// Reports NACKs as [`SimError::RemoteIo`] instead of a NACK error kind.
+    pub fn set_remote_io(&mut self, remote_io: bool) {
+        self.remote_io = remote_io;
+    }
+
+    /// A chip that is not present NACKs every transaction.
+    pub fn set_present(&mut self, present: bool) {
+        self.present = present;
+    }
+
+    /// Number of bus transactions addressed to this chip so far.
+    pub fn transactions(&self) -> usize {
+        self.transactions
+    }
+
+    fn nack(&self) -> SimError {
+        if self.remote_io {
+            SimError::RemoteIo
+        } else {
+            SimError::Nack(NoAcknowledgeSource::Address)
+        }
+    }
+
+    fn is_continuous(&self) -> bool {
+        self.config & 0x4 != 0
+    }
+
+    fn input_changed(&mut self) {
+        if self.is_continuous() {
+            self.convert();
+        }
+    }
+
+    fn shunt_register(&self) -> i16 {
+        let pga = (self.config >> 11) & 0x3;
+        let full_scale: i32 = 4000 << pga;
+        let mut value = (self.shunt_uV / 10).clamp(-full_scale, full_scale);
+        let sadc = (self.config >> 3) & 0xF;
+        // without averaging the two low bits select 9..12 bit resolution
+        if sadc & 0x8 == 0 {
+            let step = 1 << (3 - (sadc & 0x3));
+            value = value / step * step;
+        }
+        value as i16
+    }
+
+    fn bus_register(&self) -> u16 {
+        let full_scale = if self.config & CONFIG_BRNG != 0 { 32000 } else { 16000 };
+        let mut value = self.bus_mV.min(full_scale) / 4;
+        let badc = (self.config >> 7) & 0xF;
+        if badc & 0x8 == 0 {
+            let step = 1 << (3 - (badc & 0x3));
+            value = value / step * step;
+        }
+        (value as u16) << 3
+    }
+
+    fn write_register(&mut self, register: u8, value: u16) {
+        match register {
+            Register::Configuration => {
+                if value & CONFIG_RESET != 0 {
+                    self.power_cycle();
+                    return;
+                }
+                self.config = value;
+                self.bus &= !BUS_CNVR;
+                self.convert();
+            }
+            Register::Calibration => {
+                // bit 0 is not implemented and always reads back as zero
+                self.calibration = value & 0xFFFE;
+                if self.is_continuous() {
+                    self.convert();
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn read_register(&mut self) -> u16 {
+        let value = self.register(self.pointer);
+        if self.pointer == Register::Power {
+            self.bus &= !BUS_CNVR;
+        }
+        value
+    }
+}
+
+impl ErrorType for SimulatedINA219 {
+    type Error = SimError;
+}
+
+impl I2c for SimulatedINA219 {
+    fn transaction(&mut self, address: u8, operations: &mut [Operation<'_>]) -> Result<(), SimError> {
+        if address != self.address || !self.present {
+            return Err(self.nack());
+        }
+        if self.nacks > 0 {
+            self.nacks -= 1;
+            return Err(self.nack());
+        }
+        self.transactions += 1;
+
+        for operation in operations.iter_mut() {
+            match *operation {
+                Operation::Write(bytes) => {
+                    if let Some(&pointer) = bytes.first() {
+                        self.pointer = pointer;
+                    }
+                    if bytes.len() >= 3 {
+                        let value = u16::from_be_bytes([bytes[1], bytes[2]]);
+                        let pointer = self.pointer;
+                        self.write_register(pointer, value);
+                    }
+                }
+                Operation::Read(ref mut buf) => {
+                    let value = self.read_register().to_be_bytes();
+                    for (i, byte) in buf.iter_mut().enumerate() {
+                        *byte = value[i % 2];
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read(sim: &mut SimulatedINA219, register: u8) -> u16 {
+        let mut buf = [0u8; 2];
+        sim.write_read(INA219_ADDR, &[register], &mut buf).unwrap();
+        u16::from_be_bytes(buf)
+    }
+
+    fn write(sim: &mut SimulatedINA219, register: u8, value: u16) {
+        let buf = value.to_be_bytes();
+        sim.write(INA219_ADDR, &[register, buf[0], buf[1]]).unwrap();
+    }
+
+    #[test]
+    fn power_on_state() {
+        let mut sim = SimulatedINA219::new();
+        assert_eq!(read(&mut sim, Register::Configuration), CONFIG_POWER_ON_DEFAULT);
+        assert_eq!(read(&mut sim, Register::Calibration), 0);
+        assert_eq!(read(&mut sim, Register::Current), 0);
+    }
+
+    #[test]
+    fn datasheet_example() {
+        // datasheet section 8.5.1: 10 A through 2 mOhm, 12 V bus, Cal = 20480
+        let mut sim = SimulatedINA219::new();
+        sim.set_shunt_voltage_uV(20_000);
+        sim.set_bus_voltage_mV(12_000);
+        write(&mut sim, Register::Calibration, 20480);
+        assert_eq!(read(&mut sim, Register::ShuntVoltage), 2000);
+        assert_eq!(read(&mut sim, Register::BusVoltage) >> 3, 3000);
+        assert_eq!(read(&mut sim, Register::Current), 10000);
+        assert_eq!(read(&mut sim, Register::Power), 6000);
+    }
+
+    #[test]
+    fn pga_clips_shunt_voltage() {
+        let mut sim = SimulatedINA219::new();
+        // PGA /1, +-40 mV
+        write(&mut sim, Register::Configuration, 0x019F);
+        sim.set_shunt_voltage_uV(-100_000);
+        assert_eq!(read(&mut sim, Register::ShuntVoltage) as i16, -4000);
+    }
+
+    #[test]
+    fn brng_16v_saturates_bus() {
+        let mut sim = SimulatedINA219::new();
+        write(&mut sim, Register::Configuration, CONFIG_POWER_ON_DEFAULT & !CONFIG_BRNG);
+        sim.set_bus_voltage_mV(20_000);
+        assert_eq!(read(&mut sim, Register::BusVoltage) >> 3, 4000);
+    }
+
+    #[test]
+    fn overflow_and_conversion_ready_flags() {
+        let mut sim = SimulatedINA219::new();
+        sim.set_shunt_voltage_uV(320_000);
+        sim.set_bus_voltage_mV(5_000);
+        write(&mut sim, Register::Calibration, 8192);
+        let bus = read(&mut sim, Register::BusVoltage);
+        assert_eq!(bus & BUS_OVF, BUS_OVF);
+        assert_eq!(bus & BUS_CNVR, BUS_CNVR);
+        assert_eq!(read(&mut sim, Register::Current) as i16, i16::MAX);
+
+        read(&mut sim, Register::Power);
+        assert_eq!(read(&mut sim, Register::BusVoltage) & BUS_CNVR, 0);
+    }
+
+    #[test]
+    fn triggered_mode_converts_once() {
+        let mut sim = SimulatedINA219::new();
+        sim.set_bus_voltage_mV(5_000);
+        write(&mut sim, Register::Configuration, 0x3993);
+        sim.set_bus_voltage_mV(6_000);
+        assert_eq!(read(&mut sim, Register::BusVoltage) >> 3, 1250);
+        write(&mut sim, Register::Configuration, 0x3993);
+        assert_eq!(read(&mut sim, Register::BusVoltage) >> 3, 1500);
+    }
+
+    #[test]
+    fn power_down_holds_registers() {
+        let mut sim = SimulatedINA219::new();
+        sim.set_bus_voltage_mV(5_000);
+        write(&mut sim, Register::Configuration, 0x3998);
+        sim.set_bus_voltage_mV(6_000);
+        assert_eq!(read(&mut sim, Register::BusVoltage) >> 3, 1250);
+    }
+
+    #[test]
+    fn reset_bit_restores_defaults() {
+        let mut sim = SimulatedINA219::new();
+        write(&mut sim, Register::Calibration, 4096);
+        write(&mut sim, Register::Configuration, 0x019F);
+        write(&mut sim, Register::Configuration, 0x8000);
+        assert_eq!(read(&mut sim, Register::Configuration), CONFIG_POWER_ON_DEFAULT);
+        assert_eq!(read(&mut sim, Register::Calibration), 0);
+    }
+
+    #[test]
+    fn calibration_bit_zero_reads_as_zero() {
+        let mut sim = SimulatedINA219::new();
+        write(&mut sim, Register::Calibration, 0x1001);
+        assert_eq!(read(&mut sim, Register::Calibration), 0x1000);
+    }
+
+    #[test]
+    fn nack_injection() {
+        let mut sim = SimulatedINA219::new();
+        sim.nack_next(1);
+        let mut buf = [0u8; 2];
+        let err = sim.write_read(INA219_ADDR, &[0], &mut buf).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::NoAcknowledge(NoAcknowledgeSource::Address));
+        assert!(sim.write_read(INA219_ADDR, &[0], &mut buf).is_ok());
+        assert!(sim.write_read(INA219_ADDR + 1, &[0], &mut buf).is_err());
+        sim.set_present(false);
+        assert!(sim.write_read(INA219_ADDR, &[0], &mut buf).is_err());
+        assert_eq!(sim.transactions(), 1);
+    }
+}
@@ -0,0 +1,379 @@
+//! Per-board calibration kept in flash or EEPROM across reboots.
+//!
+//! The blob is versioned and CRC protected:
+//!
+//! ```text
+//! "I219" version:u8 length:u8 payload[length] crc32:u32le
+//! ```
+//!
+//! The CRC (IEEE 802.3) covers everything before it. The payload is the
+//! configuration register (u16 LE) followed by
+//! [`CalibrationRecord::to_bytes`]. Each version only appends fields, so
+//! older blobs still load, with the missing fields zero:
+//!
+//! | version | record adds                          |
+//! |---------|--------------------------------------|
+//! | 1       | shunt, LSB, calibration, offsets     |
+//! | 2       | shunt tempco, reference temperature  |
+//! | 3       | shunt tolerance                      |
+//!
+//! ```ignore
+//! // after trimming
+//! storage::store(&mut flash, 0x1F000, &StoredCalibration::of(&ina))?;
+//! // at boot
+//! storage::load(&mut flash, 0x1F000)?.apply(&mut ina)?;
+//! ```
+
+use core::fmt;
+
+use embedded_hal::i2c::I2c;
+use embedded_storage::nor_flash::{
+    check_erase, check_read, check_write, ErrorType, NorFlash, NorFlashErrorKind, ReadNorFlash,
+};
+
+use crate::error::Error;
+use crate::ina219::{CalibrationRecord, INA219};
+
+const MAGIC: &[u8; 4] = b"I219";
+pub const VERSION: u8 = 3;
+
+const HEADER_SIZE: usize = 6;
+const PAYLOAD_SIZE: usize = 2 + CalibrationRecord::SIZE;
+const CRC_SIZE: usize = 4;
+
+// payload length of each version, starting at 1
+const PAYLOAD_SIZES: [usize; 3] = [44, 56, PAYLOAD_SIZE];
+
+/// Length of an encoded blob.
+pub const BLOB_SIZE: usize = HEADER_SIZE + PAYLOAD_SIZE + CRC_SIZE;
+
+// largest flash read or write granularity supported by load and store
+const MAX_BUFFER: usize = 256;
+
+/// Everything needed to bring a freshly powered driver back to the trimmed
+/// state: configuration register, calibration, LSBs and offsets.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct StoredCalibration {
+    pub config: u16,
+    pub record: CalibrationRecord,
+}
+
+impl StoredCalibration {
+    pub fn of<I2C: I2c>(ina: &INA219<I2C>) -> StoredCalibration {
+        StoredCalibration {
+            config: ina.config(),
+            record: ina.calibration_record(),
+        }
+    }
+
+    /// Writes the configuration and calibration to the chip.
+    pub fn apply<I2C: I2c>(&self, ina: &mut INA219<I2C>) -> Result<(), Error<I2C::Error>> {
+        ina.set_config(self.config).map_err(Error::I2c)?;
+        ina.apply_calibration_record(&self.record)
+    }
+
+    pub fn to_bytes(&self) -> [u8; BLOB_SIZE] {
+        let mut blob = [0u8; BLOB_SIZE];
+        blob[..4].copy_from_slice(MAGIC);
+        blob[4] = VERSION;
+        blob[5] = PAYLOAD_SIZE as u8;
+        blob[6..8].copy_from_slice(&self.config.to_le_bytes());
+        blob[8..HEADER_SIZE + PAYLOAD_SIZE].copy_from_slice(&self.record.to_bytes());
+        let crc = crc32(&blob[..HEADER_SIZE + PAYLOAD_SIZE]);
+        blob[HEADER_SIZE + PAYLOAD_SIZE..].copy_from_slice(&crc.to_le_bytes());
+        blob
+    }
+
+    /// Decodes a blob; trailing bytes after the CRC are ignored.
+    pub fn from_bytes<E>(bytes: &[u8]) -> Result<StoredCalibration, StorageError<E>> {
+        if bytes.len() < HEADER_SIZE {
+            return Err(StorageError::Truncated);
+        }
+        if bytes[..HEADER_SIZE].iter().all(|&b| b == 0xFF) {
+            return Err(StorageError::Blank);
+        }
+        if &bytes[..4] != MAGIC {
+            return Err(StorageError::BadMagic);
+        }
+        let version = bytes[4];
+        if version == 0 || version > VERSION {
+            return Err(StorageError::UnsupportedVersion(version));
+        }
+        let length = bytes[5] as usize;
+        if length != PAYLOAD_SIZES[version as usize - 1] {
+            return Err(StorageError::Truncated);
+        }
+        let end = HEADER_SIZE + length;
+        if bytes.len() < end + CRC_SIZE {
+            return Err(StorageError::Truncated);
+        }
+        let crc = u32::from_le_bytes([bytes[end], bytes[end + 1], bytes[end + 2], bytes[end + 3]]);
+        if crc != crc32(&bytes[..end]) {
+            return Err(StorageError::Crc);
+        }
+        let mut record = [0u8; CalibrationRecord::SIZE];
+        record[..end - 8].copy_from_slice(&bytes[8..end]);
+        let record = CalibrationRecord::from_bytes(&record).ok_or(StorageError::Truncated)?;
+        Ok(StoredCalibration {
+            config: u16::from_le_bytes([bytes[6], bytes[7]]),
+            record,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StorageError<E> {
+    Flash(E),
+    /// Nothing was stored yet, the flash is erased.
+    Blank,
+    BadMagic,
+    UnsupportedVersion(u8),
+    Truncated,
+    Crc,
+    /// The flash reads or writes in units larger than `load` and `store`
+    /// buffer.
+    Granularity(usize),
+}
+
+impl<E: fmt::Debug> fmt::Display for StorageError<E> {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            StorageError::Flash(ref e) => write!(fmt, "flash error: {:?}", e),
+            StorageError::Blank => write!(fmt, "no calibration stored"),
+            StorageError::BadMagic => write!(fmt, "not a calibration blob"),
+            StorageError::UnsupportedVersion(version) => write!(fmt, "unsupported calibration blob version {}", version),
+            StorageError::Truncated => write!(fmt, "calibration blob is truncated"),
+            StorageError::Crc => write!(fmt, "calibration blob fails its CRC"),
+            StorageError::Granularity(size) => write!(fmt, "flash granularity of {} bytes is not supported", size),
+        }
+    }
+}
+
+fn round_up(n: usize, align: usize) -> usize {
+    n.div_ceil(align) * align
+}
+
+/// Erases the sectors at `offset`, which must be sector aligned, and writes
+/// the blob there.
+pub fn store<F: NorFlash>(flash: &mut F, offset: u32, calibration: &StoredCalibration) -> Result<(), StorageError<F::Error>> {
+    let length = round_up(BLOB_SIZE, F::WRITE_SIZE);
+    if length > MAX_BUFFER {
+        return Err(StorageError::Granularity(F::WRITE_SIZE));
+    }
+    let mut buffer = [0xFFu8; MAX_BUFFER];
+    buffer[..BLOB_SIZE].copy_from_slice(&calibration.to_bytes());
+    let erase_end = offset + round_up(length, F::ERASE_SIZE) as u32;
+    flash.erase(offset, erase_end).map_err(StorageError::Flash)?;
+    flash.write(offset, &buffer[..length]).map_err(StorageError::Flash)
+}
+
+pub fn load<F: ReadNorFlash>(flash: &mut F, offset: u32) -> Result<StoredCalibration, StorageError<F::Error>> {
+    let length = round_up(BLOB_SIZE, F::READ_SIZE);
+    if length > MAX_BUFFER {
+        return Err(StorageError::Granularity(F::READ_SIZE));
+    }
+    let mut buffer = [0u8; MAX_BUFFER];
+    flash.read(offset, &mut buffer[..length]).map_err(StorageError::Flash)?;
+    StoredCalibration::from_bytes(&buffer[..length])
+}
+
+// CRC-32 (IEEE 802.3), bitwise to stay small.
+fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = 0xFFFF_FFFFu32;
+    for &b in bytes {
+        crc ^= b as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xEDB8_8320 } else { crc >> 1 };
+        }
+    }
+    !crc
+}
+
+/// NOR flash in RAM, for tests: erasing sets bytes to 0xFF and writing can
+/// only clear bits, like the real thing.
+pub struct RamFlash<const N: usize> {
+    data: [u8; N],
+}
+
+impl<const N: usize> RamFlash<N> {
+    /// An erased flash.
+    pub fn new() -> RamFlash<N> {
+        RamFlash { data: [0xFF; N] }
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    pub fn data_mut(&mut self) -> &mut [u8] {
+        &mut self.data
+    }
+}
+
+impl<const N: usize> Default for RamFlash<N> {
+    fn default() -> Self {
+        RamFlash::new()
+    }
+}
+
+impl<const N: usize> ErrorType for RamFlash<N> {
+    type Error = NorFlashErrorKind;
+}
+
+impl<const N: usize> ReadNorFlash for RamFlash<N> {
+    const READ_SIZE: usize = 1;
+
+    fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), NorFlashErrorKind> {
+        check_read(self, offset, bytes.len())?;
+        let offset = offset as usize;
+        bytes.copy_from_slice(&self.data[offset..offset + bytes.len()]);
+        Ok(())
+    }
+
+    fn capacity(&self) -> usize {
+        N
+    }
+}
+
+impl<const N: usize> NorFlash for RamFlash<N> {
+    const WRITE_SIZE: usize = 4;
+    const ERASE_SIZE: usize = 256;
+
+    fn erase(&mut self, from: u32, to: u32) -> Result<(), NorFlashErrorKind> {
+        check_erase(self, from, to)?;
+        self.data[from as usize..to as usize].fill(0xFF);
+        Ok(())
+    }
+
+    fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), NorFlashErrorKind> {
+        check_write(self, offset, bytes.len())?;
+        for (cell, &b) in self.data[offset as usize..].iter_mut().zip(bytes) {
+            *cell &= b;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ina219::Calibration;
+    use crate::physic::{Celsius, MicroVolt, MilliAmpere};
+    use crate::sim::SimulatedINA219;
+
+    fn trimmed() -> INA219<SimulatedINA219> {
+        let mut ina = INA219::new(SimulatedINA219::with_inputs(50_000, 12_000));
+        ina.init(Calibration::Calibration_16V_400mA).unwrap();
+        ina.set_shunt_offset(-120 * MicroVolt);
+        ina.set_tempco(50, 20 * Celsius);
+        ina.set_shunt_tolerance(1000);
+        let point = ina.calibration_point(505 * MilliAmpere).unwrap();
+        ina.field_calibrate(&[point]).unwrap();
+        ina
+    }
+
+    #[test]
+    fn crc_matches_ieee() {
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+    }
+
+    #[test]
+    fn store_and_restore() {
+        let mut ina = trimmed();
+        let expected = ina.measure().unwrap();
+        let mut flash = RamFlash::<1024>::new();
+        assert_eq!(load(&mut flash, 256), Err(StorageError::Blank));
+        store(&mut flash, 256, &StoredCalibration::of(&ina)).unwrap();
+        // and again, over the old blob
+        store(&mut flash, 256, &StoredCalibration::of(&ina)).unwrap();
+
+        let sim = ina.release();
+        let mut booted = INA219::new(sim);
+        load(&mut flash, 256).unwrap().apply(&mut booted).unwrap();
+        assert_eq!(booted.config(), 0x019F);
+        assert_eq!(booted.calibration_record(), StoredCalibration::of(&trimmed()).record);
+        assert_eq!(booted.measure().unwrap(), expected);
+    }
+
+    #[test]
+    fn rejects_damaged_blobs() {
+        let blob = StoredCalibration::of(&trimmed()).to_bytes();
+        assert_eq!(StoredCalibration::from_bytes::<()>(&blob).map(|s| s.config), Ok(0x019F));
+
+        let mut flipped = blob;
+        flipped[10] ^= 0x04;
+        assert_eq!(StoredCalibration::from_bytes::<()>(&flipped), Err(StorageError::Crc));
+        let mut version = blob;
+        version[4] = 9;
+        assert_eq!(StoredCalibration::from_bytes::<()>(&version), Err(StorageError::UnsupportedVersion(9)));
+        assert_eq!(StoredCalibration::from_bytes::<()>(&blob[..20]), Err(StorageError::Truncated));
+        assert_eq!(StoredCalibration::from_bytes::<()>(b"ELF\0\0\0"), Err(StorageError::BadMagic));
+    }
+
+    #[test]
+    fn loads_version_1() {
+        let current = StoredCalibration::of(&trimmed());
+        let mut blob = [0u8; 54];
+        blob[..4].copy_from_slice(MAGIC);
+        blob[4] = 1;
+        blob[5] = 44;
+        blob[6..50].copy_from_slice(&current.to_bytes()[6..50]);
+        let crc = crc32(&blob[..50]);
+        blob[50..].copy_from_slice(&crc.to_le_bytes());
+
+        let old = StoredCalibration::from_bytes::<()>(&blob).unwrap();
+        assert_eq!(old.record.tempco, 0);
+        assert_eq!(old.record.reference_temperature, 0);
+        assert_eq!(old.record.shunt_tolerance, 0);
+        assert_eq!(old.record.max_current, current.record.max_current);
+        assert_eq!(old.config, current.config);
+    }
+
+    #[test]
+    fn reports_flash_errors() {
+        let mut flash = RamFlash::<1024>::new();
+        let calibration = StoredCalibration::of(&trimmed());
+        assert_eq!(store(&mut flash, 100, &calibration), Err(StorageError::Flash(NorFlashErrorKind::NotAligned)));
+        assert_eq!(store(&mut flash, 1024, &calibration), Err(StorageError::Flash(NorFlashErrorKind::OutOfBounds)));
+    }
+
+    // flash that reads and writes whole 512 byte pages
+    struct PageFlash(RamFlash<1024>);
+
+    impl ErrorType for PageFlash {
+        type Error = NorFlashErrorKind;
+    }
+
+    impl ReadNorFlash for PageFlash {
+        const READ_SIZE: usize = 512;
+
+        fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), NorFlashErrorKind> {
+            self.0.read(offset, bytes)
+        }
+
+        fn capacity(&self) -> usize {
+            self.0.capacity()
+        }
+    }
+
+    impl NorFlash for PageFlash {
+        const WRITE_SIZE: usize = 512;
+        const ERASE_SIZE: usize = 512;
+
+        fn erase(&mut self, from: u32, to: u32) -> Result<(), NorFlashErrorKind> {
+            self.0.erase(from, to)
+        }
+
+        fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), NorFlashErrorKind> {
+            self.0.write(offset, bytes)
+        }
+    }
+
+    #[test]
+    fn rejects_coarse_flash() {
+        let mut flash = PageFlash(RamFlash::new());
+        let calibration = StoredCalibration::of(&trimmed());
+        assert_eq!(store(&mut flash, 0, &calibration), Err(StorageError::Granularity(512)));
+        assert_eq!(load(&mut flash, 0), Err(StorageError::Granularity(512)));
+    }
+}
@@ -0,0 +1,412 @@
+//! Recording and replay of I2C traffic, to reproduce field sessions on a
+//! desk machine.
+//!
+//! [`Recorder`] wraps the real bus and appends every transaction to a compact
+//! binary log; [`Replay`] plays such a log back to the driver:
+//!
+//! ```ignore
+//! let mut ina = INA219::new(Recorder::new(i2c, || clock.micros()));
+//! ina.init(Calibration::Calibration_32V_2A)?;
+//! ina.measure()?;
+//! let log = ina.release().into_log();
+//!
+//! let mut ina = INA219::new(Replay::new(&log)?);
+//! ```
+//!
+//! Log layout, integers as LEB128 varints unless noted:
+//!
+//! ```text
+//! "I2CT" version:u8
+//! per transaction: time_delta_us address:u8 status:u8 operation_count
+//!     per operation: kind:u8 (0 write, 1 read) length bytes
+//! ```
+//!
+//! `status` is 0 for success, otherwise the [`ErrorKind`] the bus returned;
+//! read operations then record whatever the buffer held.
+
+use alloc::vec::Vec;
+use core::fmt;
+
+use embedded_hal::i2c::{Error, ErrorKind, ErrorType, I2c, NoAcknowledgeSource, Operation};
+
+const MAGIC: &[u8; 4] = b"I2CT";
+const VERSION: u8 = 1;
+
+const WRITE: u8 = 0;
+const READ: u8 = 1;
+
+/// Source of timestamps for the recording, in microseconds.
+pub trait Clock {
+    fn now_us(&mut self) -> u64;
+}
+
+impl<F: FnMut() -> u64> Clock for F {
+    fn now_us(&mut self) -> u64 {
+        self()
+    }
+}
+
+/// Passes transactions through to `I2C` and logs them.
+pub struct Recorder<I2C, C> {
+    i2c: I2C,
+    clock: C,
+    last: u64,
+    log: Vec<u8>,
+}
+
+impl<I2C: I2c, C: Clock> Recorder<I2C, C> {
+    pub fn new(i2c: I2C, clock: C) -> Recorder<I2C, C> {
+        let mut log = Vec::with_capacity(256);
+        log.extend_from_slice(MAGIC);
+        log.push(VERSION);
+        Recorder {
+            i2c,
+            clock,
+            last: 0,
+            log,
+        }
+    }
+
+    pub fn log(&self) -> &[u8] {
+        &self.log
+    }
+
+    pub fn into_log(self) -> Vec<u8> {
+        self.log
+    }
+
+    pub fn release(self) -> (I2C, Vec<u8>) {
+        (self.i2c, self.log)
+    }
+}
+
+impl<I2C: I2c, C> ErrorType for Recorder<I2C, C> {
+    type Error = I2C::Error;
+}
+
+impl<I2C: I2c, C: Clock> I2c for Recorder<I2C, C> {
+    fn transaction(&mut self, address: u8, operations: &mut [Operation<'_>]) -> Result<(), I2C::Error> {
+        let result = self.i2c.transaction(address, operations);
+        let now = self.clock.now_us();
+        push_varint(&mut self.log, now.saturating_sub(self.last));
+        self.last = now;
+        self.log.push(address);
+        self.log.push(match result {
+            Ok(()) => 0,
+            Err(ref e) => encode_kind(e.kind()),
+        });
+        push_varint(&mut self.log, operations.len() as u64);
+        for operation in operations.iter() {
+            let (kind, bytes): (u8, &[u8]) = match *operation {
+                Operation::Write(bytes) => (WRITE, bytes),
+                Operation::Read(ref buffer) => (READ, buffer),
+            };
+            self.log.push(kind);
+            push_varint(&mut self.log, bytes.len() as u64);
+            self.log.extend_from_slice(bytes);
+        }
+        result
+    }
+}
+
+/// One decoded operation of a [`Transaction`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Op<'a> {
+    Write(&'a [u8]),
+    Read(&'a [u8]),
+}
+
+/// One decoded transaction of a log.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Transaction<'a> {
+    /// Microseconds since the clock's epoch.
+    pub time_us: u64,
+    pub address: u8,
+    pub error: Option<ErrorKind>,
+    pub operations: Vec<Op<'a>>,
+}
+
+/// Walks the transactions of a log.
+pub struct Transactions<'a> {
+    log: &'a [u8],
+    pos: usize,
+    time_us: u64,
+}
+
+impl<'a> Transactions<'a> {
+    pub fn new(log: &'a [u8]) -> Result<Transactions<'a>, ReplayError> {
+        if log.len() < MAGIC.len() + 1 || &log[..MAGIC.len()] != MAGIC {
+            return Err(ReplayError::Corrupt(0));
+        }
+        if log[MAGIC.len()] != VERSION {
+            return Err(ReplayError::UnsupportedVersion(log[MAGIC.len()]));
+        }
+        Ok(Transactions {
+            log,
+            pos: MAGIC.len() + 1,
+            time_us: 0,
+        })
+    }
+
+    fn byte(&mut self) -> Result<u8, ReplayError> {
+        let b = *self.log.get(self.pos).ok_or(ReplayError::Corrupt(self.pos))?;
+        self.pos += 1;
+        Ok(b)
+    }
+
+    fn varint(&mut self) -> Result<u64, ReplayError> {
+        let start = self.pos;
+        let mut value = 0u64;
+        for shift in (0..64).step_by(7) {
+            let b = self.byte()?;
+            value |= u64::from(b & 0x7F) << shift;
+            if b & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(ReplayError::Corrupt(start))
+    }
+
+    fn decode(&mut self) -> Result<Transaction<'a>, ReplayError> {
+        self.time_us += self.varint()?;
+        let address = self.byte()?;
+        let status = self.byte()?;
+        let error = match status {
+            0 => None,
+            code => Some(decode_kind(code).ok_or(ReplayError::Corrupt(self.pos - 1))?),
+        };
+        let count = self.varint()? as usize;
+        let mut operations = Vec::with_capacity(count.min(16));
+        for _ in 0..count {
+            let kind = self.byte()?;
+            let len = self.varint()? as usize;
+            let end = self.pos.checked_add(len).filter(|&end| end <= self.log.len());
+            let bytes = &self.log[self.pos..end.ok_or(ReplayError::Corrupt(self.pos))?];
+            self.pos += len;
+            operations.push(match kind {
+                WRITE => Op::Write(bytes),
+                READ => Op::Read(bytes),
+                _ => return Err(ReplayError::Corrupt(self.pos - len - 1)),
+            });
+        }
+        Ok(Transaction {
+            time_us: self.time_us,
+            address,
+            error,
+            operations,
+        })
+    }
+}
+
+impl<'a> Iterator for Transactions<'a> {
+    type Item = Result<Transaction<'a>, ReplayError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.pos >= self.log.len() {
+            return None;
+        }
+        let item = self.decode();
+        if item.is_err() {
+            // nothing after a corrupt entry can be trusted
+            self.pos = self.log.len();
+        }
+        Some(item)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReplayError {
+    /// The log is damaged at this byte offset.
+    Corrupt(usize),
+    UnsupportedVersion(u8),
+    /// The driver asked for more transactions than were recorded.
+    EndOfLog,
+    /// The driver did something else than in the recording, in the
+    /// transaction with this index.
+    Mismatch(usize),
+    /// The recorded transaction failed with this error.
+    Recorded(ErrorKind),
+}
+
+impl Error for ReplayError {
+    fn kind(&self) -> ErrorKind {
+        match *self {
+            ReplayError::Recorded(kind) => kind,
+            _ => ErrorKind::Other,
+        }
+    }
+}
+
+impl fmt::Display for ReplayError {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ReplayError::Corrupt(offset) => write!(fmt, "corrupt log at byte {}", offset),
+            ReplayError::UnsupportedVersion(version) => write!(fmt, "unsupported log version {}", version),
+            ReplayError::EndOfLog => write!(fmt, "no more recorded transactions"),
+            ReplayError::Mismatch(index) => write!(fmt, "transaction {} differs from the recording", index),
+            ReplayError::Recorded(kind) => write!(fmt, "recorded bus error: {}", kind),
+        }
+    }
+}
+
+/// Answers the driver's transactions from a log made by [`Recorder`].
+pub struct Replay<'a> {
+    transactions: Transactions<'a>,
+    index: usize,
+}
+
+impl<'a> Replay<'a> {
+    pub fn new(log: &'a [u8]) -> Result<Replay<'a>, ReplayError> {
+        Ok(Replay {
+            transactions: Transactions::new(log)?,
+            index: 0,
+        })
+    }
+
+    /// Number of transactions replayed so far.
+    pub fn position(&self) -> usize {
+        self.index
+    }
+
+    /// Whether every recorded transaction has been replayed.
+    pub fn is_finished(&self) -> bool {
+        self.transactions.pos >= self.transactions.log.len()
+    }
+}
+
+impl<'a> ErrorType for Replay<'a> {
+    type Error = ReplayError;
+}
+
+impl<'a> I2c for Replay<'a> {
+    fn transaction(&mut self, address: u8, operations: &mut [Operation<'_>]) -> Result<(), ReplayError> {
+        let recorded = self.transactions.next().ok_or(ReplayError::EndOfLog)??;
+        let index = self.index;
+        self.index += 1;
+        if recorded.address != address || recorded.operations.len() != operations.len() {
+            return Err(ReplayError::Mismatch(index));
+        }
+        for (operation, op) in operations.iter_mut().zip(&recorded.operations) {
+            match (operation, op) {
+                (Operation::Write(bytes), Op::Write(recorded)) if bytes == recorded => {}
+                (Operation::Read(buffer), Op::Read(recorded)) if buffer.len() == recorded.len() => {
+                    buffer.copy_from_slice(recorded)
+                }
+                _ => return Err(ReplayError::Mismatch(index)),
+            }
+        }
+        match recorded.error {
+            None => Ok(()),
+            Some(kind) => Err(ReplayError::Recorded(kind)),
+        }
+    }
+}
+
+fn push_varint(log: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        log.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    log.push(value as u8);
+}
+
+const KINDS: [ErrorKind; 7] = [
+    ErrorKind::Bus,
+    ErrorKind::ArbitrationLoss,
+    ErrorKind::NoAcknowledge(NoAcknowledgeSource::Address),
+    ErrorKind::NoAcknowledge(NoAcknowledgeSource::Data),
+    ErrorKind::NoAcknowledge(NoAcknowledgeSource::Unknown),
+    ErrorKind::Overrun,
+    ErrorKind::Other,
+];
+
+fn encode_kind(kind: ErrorKind) -> u8 {
+    // ErrorKind is non-exhaustive, newer kinds are recorded as Other
+    KINDS.iter().position(|&k| k == kind).unwrap_or(KINDS.len() - 1) as u8 + 1
+}
+
+fn decode_kind(code: u8) -> Option<ErrorKind> {
+    KINDS.get(usize::from(code).checked_sub(1)?).copied()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ina219::{Calibration, Register, INA219};
+    use crate::sim::SimulatedINA219;
+
+    fn clock() -> impl FnMut() -> u64 {
+        let mut t = 1_000_000;
+        move || {
+            t += 1500;
+            t
+        }
+    }
+
+    fn record_session() -> (Vec<u8>, [crate::ina219::Measurement; 2]) {
+        let sim = SimulatedINA219::with_inputs(150_000, 12_000);
+        let mut ina = INA219::new(Recorder::new(sim, clock()));
+        ina.init(Calibration::Calibration_32V_2A).unwrap();
+        let first = ina.measure().unwrap();
+        let second = ina.measure().unwrap();
+        (ina.release().into_log(), [first, second])
+    }
+
+    #[test]
+    fn replay_reproduces_session() {
+        let (log, measurements) = record_session();
+        let mut ina = INA219::new(Replay::new(&log).unwrap());
+        ina.init(Calibration::Calibration_32V_2A).unwrap();
+        assert_eq!(ina.measure().unwrap(), measurements[0]);
+        assert_eq!(ina.measure().unwrap(), measurements[1]);
+        assert_eq!(ina.measure(), Err(ReplayError::EndOfLog));
+        assert!(ina.release().is_finished());
+    }
+
+    #[test]
+    fn log_is_decodable() {
+        let (log, _) = record_session();
+        let transactions: Vec<_> = Transactions::new(&log).unwrap().map(Result::unwrap).collect();
+        assert_eq!(transactions[0].time_us, 1_001_500);
+        assert_eq!(transactions[1].time_us, 1_003_000);
+        assert!(transactions.iter().all(|t| t.address == 0x40 && t.error.is_none()));
+        // every snapshot starts by pointing at the shunt voltage register
+        let last = transactions.last().unwrap();
+        assert_eq!(last.operations[0], Op::Write(&[Register::ShuntVoltage]));
+        assert_eq!(last.operations.len(), 10);
+    }
+
+    #[test]
+    fn records_bus_errors() {
+        let mut sim = SimulatedINA219::new();
+        sim.nack_next(1);
+        let mut recorder = Recorder::new(sim, || 0);
+        let mut buf = [0; 2];
+        assert!(recorder.write_read(0x40, &[Register::BusVoltage], &mut buf).is_err());
+        let log = recorder.into_log();
+
+        let mut replay = Replay::new(&log).unwrap();
+        let err = replay.write_read(0x40, &[Register::BusVoltage], &mut buf).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::NoAcknowledge(NoAcknowledgeSource::Address));
+    }
+
+    #[test]
+    fn detects_divergence() {
+        let (log, _) = record_session();
+        let mut ina = INA219::new(Replay::new(&log).unwrap());
+        assert_eq!(ina.init(Calibration::Calibration_16V_400mA), Err(ReplayError::Mismatch(0)));
+
+        let mut replay = Replay::new(&log).unwrap();
+        assert_eq!(replay.write(0x41, &[0]), Err(ReplayError::Mismatch(0)));
+    }
+
+    #[test]
+    fn rejects_bad_logs() {
+        assert_eq!(Replay::new(b"nope").err(), Some(ReplayError::Corrupt(0)));
+        assert_eq!(Replay::new(b"I2CT\x09").err(), Some(ReplayError::UnsupportedVersion(9)));
+        let (log, _) = record_session();
+        let truncated = &log[..log.len() - 1];
+        let last = Transactions::new(truncated).unwrap().last().unwrap();
+        assert!(matches!(last, Err(ReplayError::Corrupt(_))));
+    }
+}
@@ -0,0 +1,196 @@
+//! Error bars for readings, from the datasheet limits of the INA219 and the
+//! tolerance of the shunt.
+//!
+//! ```ignore
+//! ina.set_shunt_tolerance(10_000); // 1 %
+//! let model = Model::of(&ina, Spec::INA219A);
+//! let reading = model.measure(&mut ina)?;
+//! // reading.measurement.current ± reading.uncertainty.current
+//! ```
+//!
+//! Bounds are worst case: the terms add up linearly instead of as a root
+//! sum of squares.
+
+use embedded_hal::i2c::I2c;
+
+use crate::ina219::{Gain, Measurement, INA219};
+use crate::physic::{Ampere, ElectricCurrent, ElectricPotential, ElectricResistance, MicroVolt, MilliVolt, Power, Volt};
+
+const PPM: i128 = 1_000_000;
+
+/// Datasheet limits of one part.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Spec {
+    /// Maximum shunt offset voltage for the 40, 80, 160 and 320 mV ranges.
+    pub shunt_offset: [ElectricPotential; 4],
+    /// Maximum shunt voltage gain error, ppm.
+    pub shunt_gain_error: u32,
+    /// Maximum bus voltage gain error, ppm.
+    pub bus_gain_error: u32,
+}
+
+impl Spec {
+    /// INA219A, 25 °C.
+    pub const INA219A: Spec = Spec {
+        shunt_offset: [100 * MicroVolt, 125 * MicroVolt, 150 * MicroVolt, 200 * MicroVolt],
+        shunt_gain_error: 5000,
+        bus_gain_error: 5000,
+    };
+
+    /// INA219B, 25 °C.
+    pub const INA219B: Spec = Spec {
+        shunt_offset: [50 * MicroVolt, 75 * MicroVolt, 75 * MicroVolt, 100 * MicroVolt],
+        shunt_gain_error: 3000,
+        bus_gain_error: 5000,
+    };
+
+    pub fn offset(&self, gain: Gain) -> ElectricPotential {
+        match gain {
+            Gain::Gain_1_40mV => self.shunt_offset[0],
+            Gain::Gain_2_80mV => self.shunt_offset[1],
+            Gain::Gain_4_160mV => self.shunt_offset[2],
+            Gain::Gain_8_320mV => self.shunt_offset[3],
+        }
+    }
+}
+
+/// ± bounds of a [`Measurement`], in the same nano-units.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Bounds {
+    pub shunt_voltage: ElectricPotential,
+    pub bus_voltage: ElectricPotential,
+    pub current: ElectricCurrent,
+    pub power: Power,
+}
+
+/// A measurement with its error bars.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Reading {
+    pub measurement: Measurement,
+    pub uncertainty: Bounds,
+}
+
+/// Everything the bounds depend on, taken from a configured driver.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Model {
+    pub spec: Spec,
+    pub gain: Gain,
+    pub shunt: ElectricResistance,
+    /// Shunt tolerance, ppm.
+    pub shunt_tolerance: u32,
+    pub current_lsb: ElectricCurrent,
+    pub power_lsb: Power,
+    /// Half the shunt voltage step at the selected ADC resolution.
+    pub shunt_quantization: ElectricPotential,
+    /// Half the bus voltage step at the selected ADC resolution.
+    pub bus_quantization: ElectricPotential,
+}
+
+impl Model {
+    pub fn of<I2C: I2c>(ina: &INA219<I2C>, spec: Spec) -> Model {
+        let config = ina.config();
+        Model {
+            spec,
+            gain: ina.gain(),
+            shunt: ina.shunt(),
+            shunt_tolerance: ina.shunt_tolerance(),
+            current_lsb: ina.current_lsb(),
+            power_lsb: ina.power_lsb(),
+            shunt_quantization: 10 * MicroVolt * steps((config >> 3) & 0xF) / 2,
+            bus_quantization: 4 * MilliVolt * steps((config >> 7) & 0xF) / 2,
+        }
+    }
+
+    pub fn bounds(&self, m: &Measurement) -> Bounds {
+        let offset = self.spec.offset(self.gain) + self.shunt_quantization;
+        let shunt_voltage = offset + ppm_of(m.shunt_voltage, self.spec.shunt_gain_error as i128);
+        let bus_voltage = self.bus_quantization + ppm_of(m.bus_voltage, self.spec.bus_gain_error as i128);
+        let mut current = ppm_of(m.current, self.spec.shunt_gain_error as i128 + self.shunt_tolerance as i128) + self.current_lsb / 2;
+        if self.shunt > 0 {
+            current += (offset as i128 * Ampere as i128 / self.shunt as i128) as ElectricCurrent;
+        }
+        let i = m.current.unsigned_abs() as i128;
+        let v = m.bus_voltage.unsigned_abs() as i128;
+        let (di, dv) = (current as i128, bus_voltage as i128);
+        let power = (i * dv + v * di + di * dv) / Volt as i128;
+        Bounds {
+            shunt_voltage,
+            bus_voltage,
+            current,
+            power: power as Power + self.power_lsb / 2,
+        }
+    }
+
+    pub fn reading(&self, measurement: Measurement) -> Reading {
+        Reading {
+            measurement,
+            uncertainty: self.bounds(&measurement),
+        }
+    }
+
+    pub fn measure<I2C: I2c>(&self, ina: &mut INA219<I2C>) -> Result<Reading, I2C::Error> {
+        Ok(self.reading(ina.measure()?))
+    }
+}
+
+// Register steps per ADC step: 9 to 11 bit conversions fill the 12 bit
+// register in coarser steps. Averaging runs at 12 bit, it lowers noise but
+// not the ±½ LSB of the register.
+fn steps(code: u16) -> i64 {
+    if code & 0x8 == 0 {
+        1 << (3 - (code & 0x3))
+    } else {
+        1
+    }
+}
+
+fn ppm_of(value: i64, ppm: i128) -> i64 {
+    (value.unsigned_abs() as i128 * ppm / PPM) as i64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ina219::Calibration;
+    use crate::physic::{MicroAmpere, MilliAmpere, MilliWatt};
+    use crate::sim::SimulatedINA219;
+
+    #[test]
+    fn worst_case_bounds() {
+        let mut ina = INA219::new(SimulatedINA219::with_inputs(150_000, 12_000));
+        ina.init(Calibration::Calibration_32V_2A).unwrap();
+        ina.set_shunt_tolerance(10_000);
+        let reading = Model::of(&ina, Spec::INA219A).measure(&mut ina).unwrap();
+        assert_eq!(reading.measurement.current, 1500 * MilliAmpere);
+        assert_eq!(
+            reading.uncertainty,
+            Bounds {
+                // 200 µV offset, 0.5 % of 150 mV, ½ LSB
+                shunt_voltage: 955 * MicroVolt,
+                // 0.5 % of 12 V, ½ LSB
+                bus_voltage: 62 * MilliVolt,
+                // 1.5 % of 1.5 A, 205 µV / 0.1 Ω, ½ LSB
+                current: 24_600 * MicroAmpere,
+                power: 390_725_200,
+            }
+        );
+    }
+
+    #[test]
+    fn coarse_resolution_and_gain() {
+        let mut ina = INA219::new(SimulatedINA219::with_inputs(0, 0));
+        ina.init(Calibration::Calibration_32V_2A).unwrap();
+        ina.set_gain(Gain::Gain_8_320mV).unwrap();
+        let fine = Model::of(&ina, Spec::INA219B);
+        assert_eq!((fine.shunt_quantization, fine.bus_quantization), (5 * MicroVolt, 2 * MilliVolt));
+
+        // 9 bit shunt and 10 bit bus conversions
+        ina.set_config((ina.config() & !0x07F8) | 0x0080).unwrap();
+        let coarse = Model::of(&ina, Spec::INA219B);
+        assert_eq!((coarse.shunt_quantization, coarse.bus_quantization), (40 * MicroVolt, 8 * MilliVolt));
+        let zero = coarse.bounds(&Measurement::default());
+        assert_eq!(zero.shunt_voltage, 140 * MicroVolt);
+        assert_eq!(zero.current, 1450 * MicroAmpere);
+        assert_eq!(zero.power, MilliWatt + 11_600);
+    }
+}